@@ -0,0 +1,19 @@
+use std::{env, fs, io, process};
+
+use aoc22::day10;
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: day10_debug <program>");
+        process::exit(1);
+    };
+    let source = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("couldn't read {path}: {e}");
+        process::exit(1);
+    });
+    let program = day10::assemble(&source).unwrap_or_else(|e| {
+        eprintln!("{path}: {e}");
+        process::exit(1);
+    });
+    day10::debug(&program, &mut io::stdin().lock(), &mut io::stdout()).unwrap();
+}
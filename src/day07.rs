@@ -1,120 +1,821 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+    fmt,
+    fmt::Write,
+    ops::{Range, RangeInclusive},
+    str::FromStr,
+};
+
+pub type NodeId = usize;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FsPath(Vec<String>);
+
+impl FsPath {
+    pub fn root() -> FsPath {
+        FsPath(Vec::new())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    pub fn name(&self) -> &str {
+        self.0.last().map_or("/", String::as_str)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn push(&mut self, component: &str) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                self.0.pop();
+            }
+            name => self.0.push(name.to_string()),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<String> {
+        self.0.pop()
+    }
+
+    pub fn join(&self, relative: &str) -> FsPath {
+        let mut path = if relative.starts_with('/') {
+            FsPath::root()
+        } else {
+            self.clone()
+        };
+        relative.split('/').for_each(|c| path.push(c));
+        path
+    }
+}
+
+impl fmt::Display for FsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return write!(f, "/");
+        }
+        for component in &self.0 {
+            write!(f, "/{component}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FsPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.starts_with('/') {
+            return Err(format!("path is not absolute: {s}"));
+        }
+        Ok(FsPath::root().join(s))
+    }
+}
 
 #[derive(Debug)]
-pub enum DirEnt {
-    Directory(String, Vec<String>),
-    File(String, usize),
-}
-
-fn input_generator(input: &str) -> HashMap<String, DirEnt> {
-    let mut tree = HashMap::new();
-    tree.insert(
-        String::from("/"),
-        DirEnt::Directory(String::from("/"), Vec::new()),
-    );
-    let mut current_path = String::from("/");
-    for line in input.lines().skip(1) {
-        if line.starts_with('$') {
-            // Command
-            let mut words = line.split_whitespace();
-            let command = words.nth(1).unwrap();
-            let arg = words.next();
-            if command == "cd" {
-                if let Some("..") = arg {
-                    let mut path_elements: Vec<String> =
-                        current_path.split('/').map(|s| s.to_string()).collect();
-                    path_elements.pop();
-                    path_elements.pop();
-                    current_path = path_elements.join("/") + "/";
-                } else if let Some(dir) = arg {
-                    current_path += dir;
-                    current_path += "/";
-                    tree.insert(
-                        current_path.clone(),
-                        DirEnt::Directory(current_path.clone(), Vec::new()),
-                    );
+pub enum NodeKind {
+    Directory(Vec<NodeId>),
+    File(usize),
+}
+
+#[derive(Debug)]
+pub struct FsNode {
+    pub name: String,
+    pub parent: Option<NodeId>,
+    pub kind: NodeKind,
+}
+
+impl FsNode {
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, NodeKind::Directory(_))
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        match &self.kind {
+            NodeKind::Directory(children) => children,
+            NodeKind::File(_) => &[],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileSystem {
+    nodes: Vec<FsNode>,
+    sizes: Vec<usize>,
+}
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem {
+    pub const ROOT: NodeId = 0;
+
+    pub fn new() -> FileSystem {
+        FileSystem {
+            nodes: vec![FsNode {
+                name: String::from("/"),
+                parent: None,
+                kind: NodeKind::Directory(Vec::new()),
+            }],
+            sizes: vec![0],
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> &FsNode {
+        &self.nodes[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id].parent
+    }
+
+    pub fn child(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        self.nodes[id]
+            .children()
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].name == name)
+    }
+
+    fn insert(&mut self, parent: NodeId, name: &str, kind: NodeKind) -> Result<NodeId, String> {
+        if let Some(existing) = self.child(parent, name) {
+            return match (&self.nodes[existing].kind, &kind) {
+                (NodeKind::Directory(_), NodeKind::Directory(_)) => Ok(existing),
+                (NodeKind::File(old), NodeKind::File(new)) if old == new => Ok(existing),
+                _ => Err(format!(
+                    "conflicting entry for {}",
+                    self.path(parent).join(name)
+                )),
+            };
+        }
+
+        let id = self.nodes.len();
+        let size = match kind {
+            NodeKind::Directory(_) => 0,
+            NodeKind::File(size) => size,
+        };
+        match &mut self.nodes[parent].kind {
+            NodeKind::Directory(children) => children.push(id),
+            NodeKind::File(_) => return Err(format!("{} is not a directory", self.path(parent))),
+        }
+        self.nodes.push(FsNode {
+            name: name.to_string(),
+            parent: Some(parent),
+            kind,
+        });
+        // A new file adds to the size of every directory above it
+        self.sizes.push(size);
+        let mut ancestor = Some(parent);
+        while let Some(dir) = ancestor {
+            self.sizes[dir] += size;
+            ancestor = self.nodes[dir].parent;
+        }
+        Ok(id)
+    }
+
+    pub fn add_dir(&mut self, parent: NodeId, name: &str) -> Result<NodeId, String> {
+        self.insert(parent, name, NodeKind::Directory(Vec::new()))
+    }
+
+    pub fn add_file(&mut self, parent: NodeId, name: &str, size: usize) -> Result<NodeId, String> {
+        self.insert(parent, name, NodeKind::File(size))
+    }
+
+    pub fn path(&self, id: NodeId) -> FsPath {
+        let mut components = Vec::new();
+        let mut node = id;
+        while let Some(parent) = self.nodes[node].parent {
+            components.push(self.nodes[node].name.clone());
+            node = parent;
+        }
+        components.reverse();
+        FsPath(components)
+    }
+
+    // Like mkdir -p, creating every missing directory along the path
+    pub fn create_dir_all(&mut self, path: &FsPath) -> Result<NodeId, String> {
+        path.components()
+            .try_fold(Self::ROOT, |node, name| self.add_dir(node, name))
+    }
+
+    pub fn resolve(&self, path: &FsPath) -> Option<NodeId> {
+        path.components()
+            .try_fold(Self::ROOT, |node, name| self.child(node, name))
+    }
+
+    pub fn directories(&self) -> impl Iterator<Item = NodeId> + '_ {
+        (0..self.nodes.len()).filter(|&id| self.nodes[id].is_dir())
+    }
+
+    pub fn size(&self, id: NodeId) -> usize {
+        self.sizes[id]
+    }
+
+    pub fn size_of(&self, path: &FsPath) -> Option<usize> {
+        self.resolve(path).map(|id| self.size(id))
+    }
+
+    // Pre-order walk in listing order, yielding each node with its depth
+    pub fn walk(&self) -> Vec<(NodeId, usize)> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(Self::ROOT, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            order.push((id, depth));
+            for &child in self.nodes[id].children().iter().rev() {
+                stack.push((child, depth + 1));
+            }
+        }
+        order
+    }
+
+    pub fn render_tree(&self) -> String {
+        let mut rendered = String::new();
+        for (id, depth) in self.walk() {
+            let node = &self.nodes[id];
+            let indent = "  ".repeat(depth);
+            match node.kind {
+                NodeKind::Directory(_) => writeln!(rendered, "{indent}- {} (dir)", node.name),
+                NodeKind::File(size) => {
+                    writeln!(rendered, "{indent}- {} (file, size={size})", node.name)
                 }
             }
-        } else {
-            // Command output
-            let (size_or_dir, name) = line.split_once(' ').unwrap();
-            let path = current_path.clone() + name;
-            let entries =
-                if let DirEnt::Directory(_, entries) = tree.get_mut(&current_path).unwrap() {
-                    entries
-                } else {
-                    unreachable!()
+            .unwrap();
+        }
+        rendered
+    }
+
+    pub fn du_report(&self) -> String {
+        let mut dirs: Vec<_> = self.directories().map(|id| (self.size(id), id)).collect();
+        dirs.sort_by(|(size1, id1), (size2, id2)| size2.cmp(size1).then(id1.cmp(id2)));
+
+        let mut report = String::new();
+        for (size, id) in dirs {
+            writeln!(report, "{}\t{}", human_size(size), self.path(id)).unwrap();
+        }
+        report
+    }
+
+    pub fn find(&self, query: &Find) -> Vec<NodeId> {
+        self.walk()
+            .into_iter()
+            .filter(|&(id, depth)| query.matches(&self.nodes[id], self.size(id), depth))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn space_to_free(&self, disk_size: usize, target_free: usize) -> usize {
+        let free = disk_size.saturating_sub(self.size(Self::ROOT));
+        target_free.saturating_sub(free)
+    }
+
+    pub fn smallest_deletable(&self, disk_size: usize, target_free: usize) -> Option<NodeId> {
+        let needed = self.space_to_free(disk_size, target_free);
+        self.directories()
+            .filter(|&dir| self.size(dir) >= needed)
+            .min_by_key(|&dir| self.size(dir))
+    }
+
+    // Finds the set of non-nested directories freeing at least enough space
+    // while deleting as little as possible, preferring fewer directories on
+    // ties. Branch and bound over the directories from largest to smallest,
+    // starting from the best single directory as the plan to beat, and
+    // giving up with the best plan so far if the search runs too long.
+    pub fn plan_cleanup(&self, disk_size: usize, target_free: usize) -> Option<CleanupPlan> {
+        let needed = self.space_to_free(disk_size, target_free);
+        if needed == 0 {
+            return Some(CleanupPlan {
+                freed: 0,
+                directories: Vec::new(),
+                optimal: true,
+            });
+        }
+        let single = self.smallest_deletable(disk_size, target_free)?;
+
+        // A directory's subtree is the run of pre-order positions after it
+        let mut subtree_len = vec![1; self.nodes.len()];
+        for id in (1..self.nodes.len()).rev() {
+            let parent = self.nodes[id].parent.unwrap();
+            subtree_len[parent] += subtree_len[id];
+        }
+        let mut spans = vec![0..0; self.nodes.len()];
+        for (position, (id, _)) in self.walk().into_iter().enumerate() {
+            spans[id] = position..position + subtree_len[id];
+        }
+
+        let mut candidates: Vec<_> = self
+            .directories()
+            .filter(|&dir| (1..self.size(single)).contains(&self.size(dir)))
+            .collect();
+        candidates.sort_by_key(|&dir| Reverse(self.size(dir)));
+        let sizes: Vec<_> = candidates.iter().map(|&dir| self.size(dir)).collect();
+        let mut remaining = vec![0; sizes.len() + 1];
+        for i in (0..sizes.len()).rev() {
+            remaining[i] = remaining[i + 1] + sizes[i];
+        }
+
+        let mut search = CleanupSearch {
+            needed,
+            isolated: Vec::new(),
+            candidates,
+            sizes,
+            remaining,
+            spans,
+            chosen: Vec::new(),
+            budget: CleanupSearch::BUDGET,
+            best: CleanupPlan {
+                freed: self.size(single),
+                directories: vec![single],
+                optimal: true,
+            },
+        };
+        search.isolated = search
+            .candidates
+            .iter()
+            .map(|&dir| {
+                let mut others = search.candidates.iter().filter(|&&other| other != dir);
+                !others.any(|&other| search.nested(dir, other))
+            })
+            .collect();
+        search.explore(0, 0);
+
+        let mut plan = search.best;
+        plan.optimal = search.budget > 0;
+        plan.directories.sort_by_key(|&dir| search.spans[dir].start);
+        Some(plan)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanupPlan {
+    pub freed: usize,
+    pub directories: Vec<NodeId>,
+    // False if the search gave up early, in which case this is the best plan
+    // found so far
+    pub optimal: bool,
+}
+
+struct CleanupSearch {
+    needed: usize,
+    // Directories smaller than the best single one, largest first
+    candidates: Vec<NodeId>,
+    sizes: Vec<usize>,
+    // remaining[i] is the total size of candidates[i..]
+    remaining: Vec<usize>,
+    spans: Vec<Range<usize>>,
+    // Whether each candidate is unrelated to every other one, which makes
+    // it interchangeable with any other isolated candidate of the same size
+    isolated: Vec<bool>,
+    chosen: Vec<NodeId>,
+    // Branches left to explore before settling for the best plan so far
+    budget: usize,
+    best: CleanupPlan,
+}
+
+impl CleanupSearch {
+    const BUDGET: usize = 10_000_000;
+
+    fn nested(&self, a: NodeId, b: NodeId) -> bool {
+        let (a, b) = (&self.spans[a], &self.spans[b]);
+        a.start < b.end && b.start < a.end
+    }
+
+    // Fewest further candidates from next on that could cover the shortfall,
+    // ignoring nesting
+    fn fewest_more(&self, next: usize, shortfall: usize) -> usize {
+        let mut covered = 0;
+        for (count, &size) in self.sizes[next..].iter().enumerate() {
+            if covered >= shortfall {
+                return count;
+            }
+            covered += size;
+        }
+        self.sizes.len() - next
+    }
+
+    fn explore(&mut self, next: usize, freed: usize) {
+        if freed >= self.needed {
+            if (freed, self.chosen.len()) < (self.best.freed, self.best.directories.len()) {
+                self.best = CleanupPlan {
+                    freed,
+                    directories: self.chosen.clone(),
+                    optimal: true,
                 };
-            if size_or_dir == "dir" {
-                entries.push(path.clone() + "/");
-                tree.insert(
-                    path.clone() + "/",
-                    DirEnt::Directory(path.clone(), Vec::new()),
-                );
-            } else {
-                entries.push(path.clone());
-                tree.insert(
-                    path.clone(),
-                    DirEnt::File(path, size_or_dir.parse().unwrap()),
-                );
             }
+            return;
+        }
+        if self.budget == 0 {
+            return;
+        }
+        self.budget -= 1;
+        let shortfall = self.needed - freed;
+        if self.remaining[next] < shortfall {
+            return;
+        }
+        // Once nothing can free less, only a plan with fewer directories helps
+        if self.best.freed == self.needed
+            && self.chosen.len() + self.fewest_more(next, shortfall) >= self.best.directories.len()
+        {
+            return;
+        }
+
+        // Choosing one isolated candidate of a size covers every plan that
+        // would choose another of the same size instead
+        let mut tried_isolated = None;
+        for i in next..self.candidates.len() {
+            if self.remaining[i] < shortfall {
+                break;
+            }
+            if self.isolated[i] {
+                if tried_isolated == Some(self.sizes[i]) {
+                    continue;
+                }
+                tried_isolated = Some(self.sizes[i]);
+            }
+            let total = freed + self.sizes[i];
+            if total > self.best.freed
+                || (total == self.best.freed
+                    && self.chosen.len() + 1 >= self.best.directories.len())
+            {
+                continue;
+            }
+            let dir = self.candidates[i];
+            if self.chosen.iter().any(|&other| self.nested(dir, other)) {
+                continue;
+            }
+            self.chosen.push(dir);
+            self.explore(i + 1, total);
+            self.chosen.pop();
         }
     }
-    tree
 }
 
-fn part1_dir_traverser(dir: &DirEnt, dirs: &HashMap<String, DirEnt>) -> usize {
-    let ret = match dir {
-        DirEnt::Directory(_name, entries) => entries
-            .iter()
-            .map(|e| part1_dir_traverser(dirs.get(e).unwrap(), dirs))
-            .sum(),
-        DirEnt::File(_name, size) => *size,
-    };
-    ret
+fn human_size(size: usize) -> String {
+    const UNITS: [&str; 5] = ["", "K", "M", "G", "T"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    // Like du, always round up
+    if unit == 0 {
+        format!("{size}")
+    } else if value < 10.0 {
+        format!("{:.1}{}", (value * 10.0).ceil() / 10.0, UNITS[unit])
+    } else {
+        format!("{}{}", value.ceil(), UNITS[unit])
+    }
 }
 
-#[aoc(day07, part1)]
-fn solve_part1(input: &str) -> usize {
-    let tree = input_generator(input);
-    let mut sizes = Vec::new();
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // matched[j] is whether the pattern so far matches the first j characters
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    for p in pattern {
+        let mut next = vec![false; name.len() + 1];
+        match p {
+            '*' => {
+                let mut any = false;
+                for j in 0..=name.len() {
+                    any |= matched[j];
+                    next[j] = any;
+                }
+            }
+            p => {
+                for j in 1..=name.len() {
+                    next[j] = matched[j - 1] && (p == '?' || p == name[j - 1]);
+                }
+            }
+        }
+        matched = next;
+    }
+    matched[name.len()]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    Directory,
+    File,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Find {
+    size: Option<RangeInclusive<usize>>,
+    name: Option<String>,
+    depth: Option<RangeInclusive<usize>>,
+    entry_type: Option<EntryType>,
+}
+
+impl Find {
+    pub fn new() -> Find {
+        Find::default()
+    }
+
+    pub fn size(mut self, size: RangeInclusive<usize>) -> Find {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn name(mut self, glob: &str) -> Find {
+        self.name = Some(glob.to_string());
+        self
+    }
+
+    pub fn depth(mut self, depth: RangeInclusive<usize>) -> Find {
+        self.depth = Some(depth);
+        self
+    }
+
+    pub fn entry_type(mut self, entry_type: EntryType) -> Find {
+        self.entry_type = Some(entry_type);
+        self
+    }
+
+    fn matches(&self, node: &FsNode, size: usize, depth: usize) -> bool {
+        let entry_type = if node.is_dir() {
+            EntryType::Directory
+        } else {
+            EntryType::File
+        };
+        self.size.as_ref().is_none_or(|r| r.contains(&size))
+            && self.depth.as_ref().is_none_or(|r| r.contains(&depth))
+            && self.entry_type.is_none_or(|t| t == entry_type)
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|glob| glob_match(glob, &node.name))
+    }
+}
+
+enum TranscriptLine<'a> {
+    CdRoot,
+    CdUp,
+    Cd(&'a str),
+    Ls,
+    Dir(&'a str),
+    File(&'a str, usize),
+}
 
-    for key in tree.keys() {
-        if key == "/" {
-            continue;
+impl<'a> TranscriptLine<'a> {
+    fn parse(line: &'a str) -> Result<TranscriptLine<'a>, String> {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("$"), Some("cd"), Some("/"), None) => Ok(Self::CdRoot),
+            (Some("$"), Some("cd"), Some(".."), None) => Ok(Self::CdUp),
+            (Some("$"), Some("cd"), Some(dir), None) => Ok(Self::Cd(dir)),
+            (Some("$"), Some("ls"), None, None) => Ok(Self::Ls),
+            (Some("$"), ..) => Err(format!("unknown command: {line}")),
+            (Some("dir"), Some(name), None, None) => Ok(Self::Dir(name)),
+            (Some(size), Some(name), None, None) => Ok(Self::File(
+                name,
+                size.parse()
+                    .map_err(|_| format!("couldn't parse size: {size}"))?,
+            )),
+            _ => Err(format!("invalid line: {line}")),
         }
+    }
+}
 
-        if let dir @ DirEnt::Directory(..) = tree.get(key).unwrap() {
-            sizes.push(part1_dir_traverser(dir, &tree));
+impl FromStr for FileSystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fs = FileSystem::new();
+        let mut cwd = FileSystem::ROOT;
+        for (i, line) in s.lines().enumerate() {
+            let line_error = |e: String| format!("line {}: {e}", i + 1);
+            match TranscriptLine::parse(line).map_err(line_error)? {
+                TranscriptLine::CdRoot => cwd = FileSystem::ROOT,
+                TranscriptLine::CdUp => cwd = fs.parent(cwd).unwrap_or(FileSystem::ROOT),
+                TranscriptLine::Cd(dir) => {
+                    let path = fs.path(cwd).join(dir);
+                    cwd = fs.create_dir_all(&path).map_err(line_error)?;
+                }
+                TranscriptLine::Ls => {}
+                TranscriptLine::Dir(name) => {
+                    fs.add_dir(cwd, name).map_err(line_error)?;
+                }
+                TranscriptLine::File(name, size) => {
+                    fs.add_file(cwd, name, size).map_err(line_error)?;
+                }
+            }
         }
+        Ok(fs)
     }
+}
 
-    sizes.iter().filter(|size| size <= &&100000).sum()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    Malformed(String),
+    CdAboveRoot,
+    OutputWithoutLs,
+    UnlistedDirectory(FsPath),
+    FileAndDirectory { path: FsPath, first_line: usize },
+    SizeMismatch { path: FsPath, first_line: usize },
+    ListingMismatch { path: FsPath, first_line: usize },
 }
 
-#[aoc(day07, part2)]
-fn solve_part2(input: &str) -> usize {
-    let tree = input_generator(input);
-    let used = part1_dir_traverser(tree.get("/").unwrap(), &tree);
-    let max = 70000000;
-    let needed = 30000000;
-    let left = max - used;
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::Malformed(e) => write!(f, "{e}"),
+            Inconsistency::CdAboveRoot => write!(f, "cd .. from /"),
+            Inconsistency::OutputWithoutLs => write!(f, "output without a preceding ls"),
+            Inconsistency::UnlistedDirectory(path) => {
+                write!(f, "cd into {path}, which was never listed")
+            }
+            Inconsistency::FileAndDirectory { path, first_line } => write!(
+                f,
+                "{path} is both a file and a directory (first seen on line {first_line})"
+            ),
+            Inconsistency::SizeMismatch { path, first_line } => {
+                write!(f, "{path} has a different size than on line {first_line}")
+            }
+            Inconsistency::ListingMismatch { path, first_line } => write!(
+                f,
+                "{path} lists different contents than on line {first_line}"
+            ),
+        }
+    }
+}
+
+// A single `ls` of a directory, compared against the first one seen for it
+struct Listing {
+    dir: NodeId,
+    line: usize,
+    entries: BTreeSet<(String, Option<usize>)>,
+}
+
+#[derive(Default)]
+struct TranscriptChecker {
+    fs: FileSystem,
+    cwd: NodeId,
+    problems: Vec<(usize, Inconsistency)>,
+    first_seen: HashMap<NodeId, usize>,
+    first_listings: HashMap<NodeId, Listing>,
+    listing: Option<Listing>,
+}
+
+impl TranscriptChecker {
+    fn finish_listing(&mut self) {
+        let Some(listing) = self.listing.take() else {
+            return;
+        };
+        match self.first_listings.get(&listing.dir) {
+            Some(first) if first.entries != listing.entries => self.problems.push((
+                listing.line,
+                Inconsistency::ListingMismatch {
+                    path: self.fs.path(listing.dir),
+                    first_line: first.line,
+                },
+            )),
+            Some(_) => {}
+            None => {
+                self.first_listings.insert(listing.dir, listing);
+            }
+        }
+    }
+
+    fn entry(&mut self, line: usize, name: &str, size: Option<usize>) {
+        let Some(listing) = self.listing.as_mut() else {
+            self.problems.push((line, Inconsistency::OutputWithoutLs));
+            return;
+        };
+        listing.entries.insert((name.to_string(), size));
+
+        let path = self.fs.path(self.cwd).join(name);
+        let existing = self.fs.child(self.cwd, name);
+        let inserted = match size {
+            Some(size) => self.fs.add_file(self.cwd, name, size),
+            None => self.fs.add_dir(self.cwd, name),
+        };
+        match (inserted, existing) {
+            (Ok(id), _) => {
+                self.first_seen.entry(id).or_insert(line);
+            }
+            (Err(_), Some(existing)) => {
+                let first_line = self.first_seen.get(&existing).copied().unwrap_or(line);
+                let problem = if self.fs.node(existing).is_dir() == size.is_none() {
+                    Inconsistency::SizeMismatch { path, first_line }
+                } else {
+                    Inconsistency::FileAndDirectory { path, first_line }
+                };
+                self.problems.push((line, problem));
+            }
+            (Err(e), None) => self.problems.push((line, Inconsistency::Malformed(e))),
+        }
+    }
 
-    let mut smallest = max;
+    fn line(&mut self, line: usize, text: &str) {
+        let parsed = match TranscriptLine::parse(text) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.problems.push((line, Inconsistency::Malformed(e)));
+                return;
+            }
+        };
 
-    for key in tree.keys() {
-        if key == "/" {
-            continue;
+        if !matches!(parsed, TranscriptLine::Dir(_) | TranscriptLine::File(..)) {
+            self.finish_listing();
         }
 
-        if let dir @ DirEnt::Directory(..) = tree.get(key).unwrap() {
-            let dir_size = part1_dir_traverser(dir, &tree);
-            if dir_size < smallest && dir_size >= needed - left {
-                smallest = dir_size;
+        match parsed {
+            TranscriptLine::CdRoot => self.cwd = FileSystem::ROOT,
+            TranscriptLine::CdUp => match self.fs.parent(self.cwd) {
+                Some(parent) => self.cwd = parent,
+                None => self.problems.push((line, Inconsistency::CdAboveRoot)),
+            },
+            TranscriptLine::Cd(dir) => {
+                // Walk down from the root one component at a time, so that
+                // paths like a/b or /a/../b work too
+                let mut path = FsPath::root();
+                let mut node = FileSystem::ROOT;
+                for name in self.fs.path(self.cwd).join(dir).components() {
+                    path.push(name);
+                    node = match self.fs.child(node, name) {
+                        Some(child) if self.fs.node(child).is_dir() => child,
+                        Some(child) => {
+                            let first_line = self.first_seen[&child];
+                            self.problems
+                                .push((line, Inconsistency::FileAndDirectory { path, first_line }));
+                            return;
+                        }
+                        None => {
+                            self.problems
+                                .push((line, Inconsistency::UnlistedDirectory(path.clone())));
+                            self.fs.add_dir(node, name).unwrap()
+                        }
+                    };
+                }
+                self.cwd = node;
+            }
+            TranscriptLine::Ls => {
+                self.listing = Some(Listing {
+                    dir: self.cwd,
+                    line,
+                    entries: BTreeSet::new(),
+                })
             }
+            TranscriptLine::Dir(name) => self.entry(line, name, None),
+            TranscriptLine::File(name, size) => self.entry(line, name, Some(size)),
         }
     }
-    smallest
+}
+
+// Reports every inconsistency in a transcript along with its line number.
+// Conflicting entries are skipped, so later lines are still checked against
+// the first version seen.
+pub fn check_transcript(input: &str) -> Vec<(usize, Inconsistency)> {
+    let mut checker = TranscriptChecker::default();
+    for (i, line) in input.lines().enumerate() {
+        checker.line(i + 1, line);
+    }
+    checker.finish_listing();
+    // Listing mismatches are only reported once the listing ends
+    checker.problems.sort_by_key(|(line, _)| *line);
+    checker.problems
+}
+
+#[aoc_generator(day07)]
+fn input_generator(input: &str) -> FileSystem {
+    input.parse().unwrap()
+}
+
+#[aoc(day07, part1)]
+fn solve_part1(fs: &FileSystem) -> usize {
+    fs.directories()
+        .map(|dir| fs.size(dir))
+        .filter(|&size| size <= 100000)
+        .sum()
+}
+
+#[aoc(day07, part2)]
+fn solve_part2(fs: &FileSystem) -> usize {
+    let dir = fs.smallest_deletable(70000000, 30000000).unwrap();
+    fs.size(dir)
 }
 
 #[cfg(test)]
@@ -125,11 +826,310 @@ mod tests {
 
     #[test]
     fn part1() {
-        assert_eq!(solve_part1(INPUT), 95437);
+        assert_eq!(solve_part1(&input_generator(INPUT)), 95437);
     }
 
     #[test]
     fn part2() {
-        assert_eq!(solve_part2(INPUT), 24933642);
+        assert_eq!(solve_part2(&input_generator(INPUT)), 24933642);
+    }
+
+    #[test]
+    fn paths() {
+        let fs = input_generator(INPUT);
+        let e: FsPath = "/a/e".parse().unwrap();
+        let id = fs.resolve(&e).unwrap();
+        assert_eq!(fs.path(id), e);
+        assert_eq!(fs.path(id).to_string(), "/a/e");
+        assert_eq!(e.join("../../d/j"), "/d/j".parse().unwrap());
+        assert_eq!(fs.size_of(&"/d".parse().unwrap()), Some(24933642));
+    }
+
+    #[test]
+    fn sizes() {
+        let mut fs = input_generator(INPUT);
+        assert_eq!(fs.size(FileSystem::ROOT), 48381165);
+        assert_eq!(fs.size_of(&"/a".parse().unwrap()), Some(94853));
+        assert_eq!(fs.size_of(&"/a/e".parse().unwrap()), Some(584));
+        assert_eq!(fs.size_of(&"/nope".parse().unwrap()), None);
+
+        let e = fs.resolve(&"/a/e".parse().unwrap()).unwrap();
+        fs.add_file(e, "z", 16).unwrap();
+        assert_eq!(fs.size(FileSystem::ROOT), 48381181);
+        assert_eq!(fs.size(e), 600);
+        let f = fs.add_dir(e, "f").unwrap();
+        assert_eq!(fs.size(f), 0);
+        fs.add_file(f, "y", 1).unwrap();
+        assert_eq!(fs.size_of(&"/a".parse().unwrap()), Some(94870));
+    }
+
+    #[test]
+    fn render_tree() {
+        let expected = "\
+- / (dir)
+  - a (dir)
+    - e (dir)
+      - i (file, size=584)
+    - f (file, size=29116)
+    - g (file, size=2557)
+    - h.lst (file, size=62596)
+  - b.txt (file, size=14848514)
+  - c.dat (file, size=8504156)
+  - d (dir)
+    - j (file, size=4060174)
+    - d.log (file, size=8033020)
+    - d.ext (file, size=5626152)
+    - k (file, size=7214296)
+";
+        assert_eq!(input_generator(INPUT).render_tree(), expected);
+    }
+
+    #[test]
+    fn du_report() {
+        assert_eq!(
+            input_generator(INPUT).du_report(),
+            "47M\t/\n24M\t/d\n93K\t/a\n584\t/a/e\n"
+        );
+    }
+
+    #[test]
+    fn find() {
+        let fs = input_generator(INPUT);
+        let paths = |query: Find| -> Vec<String> {
+            fs.find(&query)
+                .into_iter()
+                .map(|id| fs.path(id).to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            paths(
+                Find::new()
+                    .entry_type(EntryType::Directory)
+                    .size(0..=100000)
+            ),
+            ["/a", "/a/e"]
+        );
+        assert_eq!(paths(Find::new().name("d.*")), ["/d/d.log", "/d/d.ext"]);
+        assert_eq!(
+            paths(Find::new().name("?").depth(2..=2)),
+            ["/a/e", "/a/f", "/a/g", "/d/j", "/d/k"]
+        );
+    }
+
+    #[test]
+    fn consistent_transcript() {
+        assert_eq!(check_transcript(INPUT), []);
+    }
+
+    #[test]
+    fn inconsistent_transcript() {
+        let transcript = "\
+$ cd /
+$ ls
+dir a
+10 b
+$ cd a
+$ ls
+20 c
+$ cd ..
+$ ls
+dir a
+dir b
+$ cd x
+$ cd /
+$ cd ..
+$ ls
+dir a
+11 b
+";
+        assert_eq!(
+            check_transcript(transcript),
+            [
+                (
+                    9,
+                    Inconsistency::ListingMismatch {
+                        path: FsPath::root(),
+                        first_line: 2
+                    }
+                ),
+                (
+                    11,
+                    Inconsistency::FileAndDirectory {
+                        path: "/b".parse().unwrap(),
+                        first_line: 4
+                    }
+                ),
+                (12, Inconsistency::UnlistedDirectory("/x".parse().unwrap())),
+                (14, Inconsistency::CdAboveRoot),
+                (
+                    15,
+                    Inconsistency::ListingMismatch {
+                        path: FsPath::root(),
+                        first_line: 2
+                    }
+                ),
+                (
+                    17,
+                    Inconsistency::SizeMismatch {
+                        path: "/b".parse().unwrap(),
+                        first_line: 4
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn cleanup_plan() {
+        let fs = input_generator(INPUT);
+        let d = fs.resolve(&"/d".parse().unwrap()).unwrap();
+        let plan = fs.plan_cleanup(70000000, 30000000).unwrap();
+        assert_eq!(plan.directories, [d]);
+        assert!(fs.plan_cleanup(70000000, 0).unwrap().directories.is_empty());
+
+        let transcript = "\
+$ cd /
+$ ls
+dir x
+dir y
+dir z
+$ cd x
+$ ls
+60 a
+dir w
+$ cd w
+$ ls
+10 b
+$ cd /
+$ cd y
+$ ls
+50 c
+$ cd ..
+$ cd z
+$ ls
+120 d
+";
+        let fs = input_generator(transcript);
+        let dirs = |plan: CleanupPlan| -> Vec<String> {
+            plan.directories
+                .into_iter()
+                .map(|id| fs.path(id).to_string())
+                .collect()
+        };
+
+        // Total 240, so a target of 100 on a 240 disk needs 100 freed
+        let z = fs.smallest_deletable(240, 100).unwrap();
+        assert_eq!(fs.path(z).to_string(), "/z");
+        let plan = fs.plan_cleanup(240, 100).unwrap();
+        assert_eq!(plan.freed, 120);
+        assert_eq!(dirs(plan), ["/z"]);
+        let plan = fs.plan_cleanup(240, 110).unwrap();
+        assert_eq!(plan.freed, 120);
+        assert_eq!(dirs(plan), ["/z"]);
+        let plan = fs.plan_cleanup(240, 55).unwrap();
+        assert_eq!(plan.freed, 60);
+        assert_eq!(dirs(plan), ["/x/w", "/y"]);
+        assert_eq!(fs.plan_cleanup(240, 300), None);
+    }
+
+    #[test]
+    fn cleanup_plan_at_scale() {
+        // A few hundred directories of up to a few hundred KB each, built from
+        // a fixed pseudo-random sequence
+        let mut seed: u64 = 2022;
+        let mut random = |n: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+        let mut fs = FileSystem::new();
+        let mut dirs = vec![FileSystem::ROOT];
+        for i in 0..250 {
+            let parent = dirs[random(dirs.len())];
+            let dir = fs.add_dir(parent, &format!("d{i}")).unwrap();
+            for j in 0..1 + random(3) {
+                fs.add_file(dir, &format!("f{j}"), 1 + random(100_000))
+                    .unwrap();
+            }
+            dirs.push(dir);
+        }
+
+        let used = fs.size(FileSystem::ROOT);
+        let single = fs.smallest_deletable(used, 8_000_000).unwrap();
+        let plan = fs.plan_cleanup(used, 8_000_000).unwrap();
+        assert!(plan.freed >= 8_000_000 && plan.freed <= fs.size(single));
+        let freed: usize = plan.directories.iter().map(|&dir| fs.size(dir)).sum();
+        assert_eq!(freed, plan.freed);
+        for &a in &plan.directories {
+            for &b in &plan.directories {
+                let mut ancestor = fs.parent(b);
+                while let Some(id) = ancestor {
+                    assert_ne!(id, a);
+                    ancestor = fs.parent(id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cleanup_plan_equal_sizes() {
+        // Many small directories that can never beat the large one, which
+        // used to be tried in every combination
+        let mut fs = FileSystem::new();
+        let big = fs.add_dir(FileSystem::ROOT, "big").unwrap();
+        fs.add_file(big, "f", 42).unwrap();
+        for i in 0..32 {
+            let dir = fs.add_dir(FileSystem::ROOT, &format!("d{i}")).unwrap();
+            fs.add_file(dir, "f", 2).unwrap();
+        }
+
+        let used = fs.size(FileSystem::ROOT);
+        let plan = fs.plan_cleanup(used, 41).unwrap();
+        assert_eq!(plan.freed, 42);
+        assert_eq!(plan.directories, [big]);
+        assert!(plan.optimal);
+    }
+
+    #[test]
+    fn absolute_cd_and_repeated_ls() {
+        let transcript =
+            INPUT.to_string() + "$ cd /\n$ ls\ndir a\n14848514 b.txt\n$ cd a\n$ ls\n29116 f\n";
+        let fs = input_generator(&transcript);
+        assert_eq!(fs.len(), input_generator(INPUT).len());
+        assert_eq!(solve_part1(&fs), 95437);
+    }
+
+    #[test]
+    fn cd_paths() {
+        let transcript = "\
+$ cd /
+$ ls
+dir a
+$ cd a
+$ ls
+dir b
+$ cd /a/b
+$ ls
+10 x
+$ cd ../../a/b/c
+$ ls
+5 y
+";
+        let fs = input_generator(transcript);
+        assert_eq!(fs.len(), 6);
+        let c = fs.resolve(&"/a/b/c".parse().unwrap()).unwrap();
+        assert_eq!(fs.size(c), 5);
+        assert_eq!(fs.size_of(&"/a".parse().unwrap()), Some(15));
+        assert!(fs
+            .walk()
+            .iter()
+            .skip(1)
+            .all(|&(id, _)| !fs.node(id).name.contains('/')));
+
+        let problems = check_transcript(transcript);
+        let c = "/a/b/c".parse().unwrap();
+        assert_eq!(problems, [(10, Inconsistency::UnlistedDirectory(c))]);
     }
 }
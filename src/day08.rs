@@ -1,3 +1,8 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
 use grid::{grid, Grid};
 
 type Trees = Grid<u8>;
@@ -27,18 +32,17 @@ fn visible_trees(direction: &mut dyn Iterator<Item = &u8>) -> Vec<usize> {
     visible
 }
 
-#[aoc(day08, part1)]
-fn solve_part1(trees: &Trees) -> usize {
-    let mut seen = Grid::init(trees.rows(), trees.cols(), 0);
+pub fn visibility(trees: &Trees) -> Grid<bool> {
+    let mut seen = Grid::init(trees.rows(), trees.cols(), false);
 
     for row in 0..trees.rows() {
         let forward = visible_trees(&mut trees.iter_row(row));
         let reverse = visible_trees(&mut trees.iter_row(row).rev());
         for col in forward {
-            *seen.get_mut(row, col).unwrap() = 1;
+            *seen.get_mut(row, col).unwrap() = true;
         }
         for col in reverse {
-            *seen.get_mut(row, trees.cols() - 1 - col).unwrap() = 1;
+            *seen.get_mut(row, trees.cols() - 1 - col).unwrap() = true;
         }
     }
 
@@ -46,46 +50,229 @@ fn solve_part1(trees: &Trees) -> usize {
         let forward = visible_trees(&mut trees.iter_col(col));
         let reverse = visible_trees(&mut trees.iter_col(col).rev());
         for row in forward {
-            *seen.get_mut(row, col).unwrap() = 1;
+            *seen.get_mut(row, col).unwrap() = true;
         }
         for row in reverse {
-            *seen.get_mut(trees.rows() - 1 - row, col).unwrap() = 1;
+            *seen.get_mut(trees.rows() - 1 - row, col).unwrap() = true;
         }
     }
 
-    seen.iter().sum()
+    seen
 }
 
-fn viewing_direction(direction: &mut dyn Iterator<Item = &u8>) -> usize {
-    let height = direction.next().unwrap();
-    let mut viewing_distance = 0;
-    for tree in direction {
-        viewing_distance += 1;
-        if tree >= height {
-            break;
+#[aoc(day08, part1)]
+fn solve_part1(trees: &Trees) -> usize {
+    visibility(trees).iter().filter(|&&seen| seen).count()
+}
+
+// Distance from each tree back along the line to the first tree at least as
+// tall, or to the edge. Trees shorter than the current one can never block
+// anything further along, so they are popped off the stack for good.
+fn viewing_distances(line: &mut dyn Iterator<Item = &u8>) -> Vec<usize> {
+    let mut distances = Vec::new();
+    let mut stack: Vec<(usize, u8)> = Vec::new();
+    for (i, &height) in line.enumerate() {
+        while stack.last().is_some_and(|&(_, blocker)| blocker < height) {
+            stack.pop();
         }
+        distances.push(stack.last().map_or(i, |&(blocker, _)| i - blocker));
+        stack.push((i, height));
     }
-    viewing_distance
+    distances
 }
 
-#[aoc(day08, part2)]
-fn solve_part2(trees: &Trees) -> usize {
-    let mut best_score = 0;
+pub fn scenic_scores(trees: &Trees) -> Grid<usize> {
+    let mut scores = Grid::init(trees.rows(), trees.cols(), 1);
+
     for row in 0..trees.rows() {
+        let left = viewing_distances(&mut trees.iter_row(row));
+        let right = viewing_distances(&mut trees.iter_row(row).rev());
         for col in 0..trees.cols() {
-            let up_score =
-                viewing_direction(&mut trees.iter_col(col).rev().skip(trees.rows() - 1 - row));
-            let left_score =
-                viewing_direction(&mut trees.iter_row(row).rev().skip(trees.cols() - 1 - col));
-            let down_score = viewing_direction(&mut trees.iter_col(col).skip(row));
-            let right_score = viewing_direction(&mut trees.iter_row(row).skip(col));
-            let view_score = left_score * right_score * up_score * down_score;
-            if view_score > best_score {
-                best_score = view_score;
+            *scores.get_mut(row, col).unwrap() *= left[col] * right[trees.cols() - 1 - col];
+        }
+    }
+
+    for col in 0..trees.cols() {
+        let up = viewing_distances(&mut trees.iter_col(col));
+        let down = viewing_distances(&mut trees.iter_col(col).rev());
+        for row in 0..trees.rows() {
+            *scores.get_mut(row, col).unwrap() *= up[row] * down[trees.rows() - 1 - row];
+        }
+    }
+
+    scores
+}
+
+// The k highest scoring (row, col) locations, best first
+pub fn best_locations(scores: &Grid<usize>, k: usize) -> Vec<((usize, usize), usize)> {
+    let mut locations: Vec<_> = scores
+        .iter()
+        .enumerate()
+        .map(|(i, &score)| ((i / scores.cols(), i % scores.cols()), score))
+        .collect();
+    locations.sort_by(|(_, score1), (_, score2)| score2.cmp(score1));
+    locations.truncate(k);
+    locations
+}
+
+#[aoc(day08, part2)]
+fn solve_part2(trees: &Trees) -> usize {
+    scenic_scores(trees).iter().copied().max().unwrap_or(0)
+}
+
+pub const COMPASS: [(isize, isize); 8] = [
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+];
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Someone standing on top of the tree at (row, col) with their eyes
+// eye_height above it
+#[derive(Debug, Clone, Copy)]
+pub struct Observer {
+    pub row: usize,
+    pub col: usize,
+    pub eye_height: u8,
+}
+
+impl Observer {
+    // Trees whose tops can be seen looking along the ray (d_row, d_col). Only
+    // trees the ray passes straight through are considered, i.e. every
+    // multiple of the reduced step.
+    pub fn visible_along(
+        &self,
+        trees: &Trees,
+        (d_row, d_col): (isize, isize),
+    ) -> Vec<(usize, usize)> {
+        let step = gcd(d_row.unsigned_abs(), d_col.unsigned_abs()) as isize;
+        if step == 0 {
+            return Vec::new();
+        }
+        let (d_row, d_col) = (d_row / step, d_col / step);
+        let Some(&ground) = trees.get(self.row, self.col) else {
+            return Vec::new();
+        };
+        let eye = ground as i64 + self.eye_height as i64;
+
+        let mut visible = Vec::new();
+        // Steepest elevation so far as the fraction rise / distance
+        let mut steepest: Option<(i64, i64)> = None;
+        for distance in 1.. {
+            let row = self.row as isize + distance * d_row;
+            let col = self.col as isize + distance * d_col;
+            if row < 0 || col < 0 {
+                break;
+            }
+            let Some(&height) = trees.get(row as usize, col as usize) else {
+                break;
+            };
+            let rise = height as i64 - eye;
+            let distance = distance as i64;
+            if steepest
+                .is_none_or(|(max_rise, max_distance)| rise * max_distance > max_rise * distance)
+            {
+                steepest = Some((rise, distance));
+                visible.push((row as usize, col as usize));
+            }
+        }
+        visible
+    }
+
+    pub fn visible_from(&self, trees: &Trees, rays: &[(isize, isize)]) -> Grid<bool> {
+        let mut seen = Grid::init(trees.rows(), trees.cols(), false);
+        for &ray in rays {
+            for (row, col) in self.visible_along(trees, ray) {
+                *seen.get_mut(row, col).unwrap() = true;
+            }
+        }
+        seen
+    }
+}
+
+pub type Rgb = (u8, u8, u8);
+
+// Blue through green to red as value goes from 0 to max
+fn heat_colour(value: usize, max: usize) -> Rgb {
+    let t = if max == 0 {
+        0.0
+    } else {
+        value as f64 / max as f64
+    };
+    let (r, g, b) = if t < 0.5 {
+        (0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        (2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    };
+    (
+        (r * 255.0).round() as u8,
+        (g * 255.0).round() as u8,
+        (b * 255.0).round() as u8,
+    )
+}
+
+pub fn visibility_heatmap(visibility: &Grid<bool>) -> Grid<Rgb> {
+    let mut image = Grid::init(visibility.rows(), visibility.cols(), (0, 0, 0));
+    for (pixel, &seen) in image.iter_mut().zip(visibility.iter()) {
+        *pixel = if seen { (255, 255, 255) } else { (0, 96, 0) };
+    }
+    image
+}
+
+pub fn scenic_heatmap(scores: &Grid<usize>) -> Grid<Rgb> {
+    let max = scores.iter().copied().max().unwrap_or(0);
+    let mut image = Grid::init(scores.rows(), scores.cols(), (0, 0, 0));
+    for (pixel, &score) in image.iter_mut().zip(scores.iter()) {
+        *pixel = heat_colour(score, max);
+    }
+    image
+}
+
+// Binary PPM (P6), with every cell drawn as a scale x scale block
+pub fn write_ppm(image: &Grid<Rgb>, scale: usize, out: &mut dyn Write) -> io::Result<()> {
+    write!(
+        out,
+        "P6\n{} {}\n255\n",
+        image.cols() * scale,
+        image.rows() * scale
+    )?;
+    for row in 0..image.rows() {
+        let mut line = Vec::with_capacity(image.cols() * scale * 3);
+        for &(r, g, b) in image.iter_row(row) {
+            for _ in 0..scale {
+                line.extend([r, g, b]);
             }
         }
+        for _ in 0..scale {
+            out.write_all(&line)?;
+        }
+    }
+    Ok(())
+}
+
+// Tree heights drawn over the heatmap using 24-bit ANSI background colours
+pub fn render_ansi(trees: &Trees, image: &Grid<Rgb>) -> String {
+    let mut rendered = String::new();
+    for row in 0..trees.rows() {
+        for (height, (r, g, b)) in trees.iter_row(row).zip(image.iter_row(row)) {
+            let esc = 27 as char;
+            write!(rendered, "{esc}[48;2;{r};{g};{b}m{height}").unwrap();
+        }
+        writeln!(rendered, "{}[0m", 27 as char).unwrap();
     }
-    best_score
+    rendered
 }
 
 #[cfg(test)]
@@ -103,4 +290,64 @@ mod tests {
     fn part2() {
         assert_eq!(solve_part2(&input_generator(INPUT)), 8);
     }
+
+    #[test]
+    fn scenic_scores() {
+        let scores = super::scenic_scores(&input_generator(INPUT));
+        assert_eq!(scores.get(1, 2), Some(&4));
+        assert_eq!(scores.get(3, 2), Some(&8));
+        assert_eq!(scores.iter_row(0).sum::<usize>(), 0);
+        assert_eq!(best_locations(&scores, 2), [((3, 2), 8), ((2, 1), 6)]);
+    }
+
+    #[test]
+    fn line_of_sight() {
+        let trees = input_generator(INPUT);
+        let observer = Observer {
+            row: 3,
+            col: 2,
+            eye_height: 0,
+        };
+        assert_eq!(observer.visible_along(&trees, (-1, 0)), [(2, 2), (1, 2)]);
+        assert_eq!(observer.visible_along(&trees, (-2, 0)), [(2, 2), (1, 2)]);
+        assert_eq!(observer.visible_along(&trees, (-1, -2)), [(2, 0)]);
+        assert_eq!(observer.visible_along(&trees, (0, 0)), []);
+
+        let tall = Observer {
+            eye_height: 100,
+            ..observer
+        };
+        let seen = tall.visible_from(&trees, &COMPASS);
+        assert_eq!(seen.iter().filter(|&&seen| seen).count(), 14);
+
+        // Eyes above the tallest tree see the top of everything along a ray
+        let towering = Observer {
+            eye_height: 250,
+            ..observer
+        };
+        assert_eq!(towering.visible_along(&trees, (0, 1)), [(3, 3), (3, 4)]);
+        let outside = Observer { row: 5, ..observer };
+        assert_eq!(outside.visible_along(&trees, (-1, 0)), []);
+    }
+
+    #[test]
+    fn heatmaps() {
+        let trees = input_generator(INPUT);
+        let image = visibility_heatmap(&visibility(&trees));
+        assert_eq!(image.get(2, 2), Some(&(0, 96, 0)));
+        assert_eq!(image.get(1, 1), Some(&(255, 255, 255)));
+
+        let image = scenic_heatmap(&super::scenic_scores(&trees));
+        assert_eq!(image.get(3, 2), Some(&(255, 0, 0)));
+        assert_eq!(image.get(0, 0), Some(&(0, 0, 255)));
+
+        let mut ppm = Vec::new();
+        write_ppm(&image, 2, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n10 10\n255\n"));
+        assert_eq!(ppm.len(), "P6\n10 10\n255\n".len() + 10 * 10 * 3);
+
+        let ansi = render_ansi(&trees, &image);
+        assert_eq!(ansi.lines().count(), 5);
+        assert!(ansi.starts_with("\x1b[48;2;0;0;255m3"));
+    }
 }
@@ -1,15 +1,39 @@
-use itertools::Itertools;
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, Write},
+};
 
-enum Direction {
+pub enum Direction {
     Up,
     Down,
     Left,
     Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
 }
 
-struct Move {
-    direction: Direction,
-    steps: isize,
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self {
+            Direction::Up => "U",
+            Direction::Down => "D",
+            Direction::Left => "L",
+            Direction::Right => "R",
+            Direction::UpLeft => "UL",
+            Direction::UpRight => "UR",
+            Direction::DownLeft => "DL",
+            Direction::DownRight => "DR",
+        };
+        write!(f, "{direction}")
+    }
+}
+
+pub struct Move {
+    pub direction: Direction,
+    pub steps: isize,
 }
 
 #[aoc_generator(day09)]
@@ -23,6 +47,10 @@ fn input_generator(input: &str) -> Vec<Move> {
                 "D" => Direction::Down,
                 "L" => Direction::Left,
                 "R" => Direction::Right,
+                "UL" => Direction::UpLeft,
+                "UR" => Direction::UpRight,
+                "DL" => Direction::DownLeft,
+                "DR" => Direction::DownRight,
                 _ => unreachable!(),
             };
             Move {
@@ -33,58 +61,406 @@ fn input_generator(input: &str) -> Vec<Move> {
         .collect()
 }
 
-fn new_tail_position(head: &(isize, isize), tail: &(isize, isize)) -> (isize, isize) {
-    if head.0.abs_diff(tail.0) > 1 || head.1.abs_diff(tail.1) > 1 {
-        (
-            tail.0 + (head.0 - tail.0).signum(),
-            tail.1 + (head.1 - tail.1).signum(),
-        )
-    } else {
-        *tail
+pub type Position = (isize, isize);
+pub type Bounds = (Position, Position);
+
+fn touching(a: &Position, b: &Position) -> bool {
+    a.0.abs_diff(b.0) <= 1 && a.1.abs_diff(b.1) <= 1
+}
+
+// How a knot moves after the knot in front of it (the leader) has moved.
+// Bounded ropes require followers to stay within the box the head has been
+// in, which holds for any rule that only moves towards the leader or to where
+// the leader has already been.
+pub trait FollowRule {
+    fn follow(&self, leader: Position, previous_leader: Position, follower: Position) -> Position;
+}
+
+// The puzzle's rule: step straight or diagonally towards the leader
+pub struct DiagonalFollow;
+
+impl FollowRule for DiagonalFollow {
+    fn follow(&self, leader: Position, _: Position, follower: Position) -> Position {
+        if touching(&leader, &follower) {
+            follower
+        } else {
+            (
+                follower.0 + (leader.0 - follower.0).signum(),
+                follower.1 + (leader.1 - follower.1).signum(),
+            )
+        }
+    }
+}
+
+// Only ever step along one axis, keeping the follower orthogonally adjacent
+// to the leader
+pub struct OrthogonalFollow;
+
+impl FollowRule for OrthogonalFollow {
+    fn follow(&self, leader: Position, _: Position, follower: Position) -> Position {
+        let dx = leader.0 - follower.0;
+        let dy = leader.1 - follower.1;
+        if dx.abs() + dy.abs() <= 1 {
+            follower
+        } else if dx.abs() >= dy.abs() {
+            (follower.0 + dx.signum(), follower.1)
+        } else {
+            (follower.0, follower.1 + dy.signum())
+        }
     }
 }
 
-#[aoc(day09, part1)]
-fn solve_part1(moves: &[Move]) -> usize {
-    let mut tail_positions = vec![(0, 0)];
-    let mut head_position = (0, 0);
-    let mut tail_position = (0, 0);
+// Snake-like: once the leader gets away, jump to where it just was
+pub struct LaggedFollow;
+
+impl FollowRule for LaggedFollow {
+    fn follow(&self, leader: Position, previous_leader: Position, follower: Position) -> Position {
+        if touching(&leader, &follower) {
+            follower
+        } else {
+            previous_leader
+        }
+    }
+}
+
+impl Direction {
+    fn offset(&self) -> Position {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::UpLeft => (-1, -1),
+            Direction::UpRight => (1, -1),
+            Direction::DownLeft => (-1, 1),
+            Direction::DownRight => (1, 1),
+        }
+    }
+}
+
+// Every knot moves towards the one in front of it, so no knot can ever leave
+// the box the head has been in. One pass over the moves is enough to size
+// the visited bitmaps.
+pub fn head_bounds(moves: &[Move]) -> Bounds {
+    let mut head = (0, 0);
+    let mut bounds = ((0, 0), (0, 0));
     for Move { direction, steps } in moves {
-        for _ in 0..*steps {
-            match direction {
-                Direction::Up => head_position.1 -= 1,
-                Direction::Down => head_position.1 += 1,
-                Direction::Left => head_position.0 -= 1,
-                Direction::Right => head_position.0 += 1,
+        let (dx, dy) = direction.offset();
+        head = (head.0 + dx * steps, head.1 + dy * steps);
+        bounds = extend_bounds(bounds, head);
+    }
+    bounds
+}
+
+fn extend_bounds(((min_x, min_y), (max_x, max_y)): Bounds, (x, y): Position) -> Bounds {
+    ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+}
+
+struct Bitmap {
+    origin: Position,
+    width: usize,
+    height: usize,
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    fn new(((min_x, min_y), (max_x, max_y)): Bounds) -> Bitmap {
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+        Bitmap {
+            origin: (min_x, min_y),
+            width,
+            height,
+            words: vec![0; (width * height).div_ceil(64)],
+        }
+    }
+
+    fn index(&self, (x, y): Position) -> Option<usize> {
+        let x = usize::try_from(x - self.origin.0).ok()?;
+        let y = usize::try_from(y - self.origin.1).ok()?;
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    // Returns whether the position was newly set
+    fn insert(&mut self, position: Position) -> bool {
+        let i = self.index(position).expect("position outside of bitmap");
+        let bit = 1 << (i % 64);
+        let was_set = self.words[i / 64] & bit != 0;
+        self.words[i / 64] |= bit;
+        !was_set
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.index(position)
+            .is_some_and(|i| self.words[i / 64] & (1 << (i % 64)) != 0)
+    }
+}
+
+enum VisitedCells {
+    Bitmap(Bitmap, usize),
+    Set(HashSet<Position>),
+}
+
+impl VisitedCells {
+    fn insert(&mut self, position: Position) {
+        match self {
+            VisitedCells::Bitmap(bitmap, count) => {
+                if bitmap.insert(position) {
+                    *count += 1;
+                }
+            }
+            VisitedCells::Set(set) => {
+                set.insert(position);
             }
-            tail_position = new_tail_position(&head_position, &tail_position);
-            tail_positions.push(tail_position);
         }
     }
 
-    tail_positions.iter().unique().count()
+    fn contains(&self, position: Position) -> bool {
+        match self {
+            VisitedCells::Bitmap(bitmap, _) => bitmap.contains(position),
+            VisitedCells::Set(set) => set.contains(&position),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            VisitedCells::Bitmap(_, count) => *count,
+            VisitedCells::Set(set) => set.len(),
+        }
+    }
 }
 
-#[aoc(day09, part2)]
-fn solve_part2(moves: &[Move]) -> usize {
-    let mut tail_positions = vec![(0, 0)];
-    let mut knots = vec![(0, 0); 10];
-    for Move { direction, steps } in moves {
+pub struct Rope {
+    knots: Vec<Position>,
+    rule: Box<dyn FollowRule>,
+    // None for knots whose visited cells aren't being tracked
+    visited: Vec<Option<VisitedCells>>,
+    trace: Option<Vec<Vec<Position>>>,
+    bounds: Bounds,
+}
+
+impl Rope {
+    // Tracks visited cells in hash sets, for when the moves aren't known up
+    // front
+    pub fn new(length: usize) -> Rope {
+        Self::with_visited(length, || VisitedCells::Set(HashSet::new()))
+    }
+
+    // Tracks visited cells in bitmaps covering the given bounds. Stepping
+    // outside of them panics.
+    pub fn bounded(length: usize, bounds: Bounds) -> Rope {
+        Self::with_visited(length, || VisitedCells::Bitmap(Bitmap::new(bounds), 0))
+    }
+
+    // Bitmaps when the moves stay within a compact area, otherwise hash sets,
+    // so that a few long moves don't need a bitmap the size of their bounding
+    // box
+    pub fn for_moves(length: usize, moves: &[Move]) -> Rope {
+        const CELLS_PER_STEP: usize = 64;
+        let ((min_x, min_y), (max_x, max_y)) = head_bounds(moves);
+        let area = (max_x - min_x + 1) as usize * (max_y - min_y + 1) as usize;
+        let steps: usize = moves.iter().map(|m| m.steps as usize).sum();
+        if area <= CELLS_PER_STEP * steps.max(64) {
+            Self::bounded(length, head_bounds(moves))
+        } else {
+            Self::new(length)
+        }
+    }
+
+    // Stop tracking visited cells for every knot except the given ones
+    pub fn tracking_only(mut self, knots: &[usize]) -> Rope {
+        for (knot, visited) in self.visited.iter_mut().enumerate() {
+            if !knots.contains(&knot) {
+                *visited = None;
+            }
+        }
+        self
+    }
+
+    fn with_visited<F>(length: usize, visited: F) -> Rope
+    where
+        F: Fn() -> VisitedCells,
+    {
+        assert!(length > 0, "a rope needs at least one knot");
+        let mut rope = Rope {
+            knots: vec![(0, 0); length],
+            rule: Box::new(DiagonalFollow),
+            visited: (0..length).map(|_| Some(visited())).collect(),
+            trace: None,
+            bounds: ((0, 0), (0, 0)),
+        };
+        rope.visited
+            .iter_mut()
+            .flatten()
+            .for_each(|v| v.insert((0, 0)));
+        rope
+    }
+
+    pub fn with_rule<R: FollowRule + 'static>(mut self, rule: R) -> Rope {
+        self.rule = Box::new(rule);
+        self
+    }
+
+    // Also keep every position of every knot, one entry per step
+    pub fn with_trace(mut self) -> Rope {
+        self.trace = Some(vec![vec![(0, 0)]; self.knots.len()]);
+        self
+    }
+
+    pub fn knots(&self) -> &[Position] {
+        &self.knots
+    }
+
+    pub fn head(&self) -> Position {
+        self.knots[0]
+    }
+
+    pub fn tail(&self) -> Position {
+        *self.knots.last().unwrap()
+    }
+
+    pub fn trace(&self, knot: usize) -> Option<&[Position]> {
+        self.trace.as_ref().map(|trace| trace[knot].as_slice())
+    }
+
+    fn visited_cells(&self, knot: usize) -> &VisitedCells {
+        self.visited[knot]
+            .as_ref()
+            .unwrap_or_else(|| panic!("knot {knot} isn't being tracked"))
+    }
+
+    pub fn visited(&self, knot: usize) -> usize {
+        self.visited_cells(knot).len()
+    }
+
+    pub fn has_visited(&self, knot: usize, position: Position) -> bool {
+        self.visited_cells(knot).contains(position)
+    }
+
+    // Smallest (top left, bottom right) box containing everywhere any knot
+    // has been, including the start
+    pub fn bounds(&self) -> Bounds {
+        self.bounds
+    }
+
+    pub fn step(&mut self, direction: &Direction) {
+        let (dx, dy) = direction.offset();
+        let mut previous = self.knots[0];
+        self.knots[0].0 += dx;
+        self.knots[0].1 += dy;
+        self.bounds = extend_bounds(self.bounds, self.knots[0]);
+
+        for i in 1..self.knots.len() {
+            let moved = self.rule.follow(self.knots[i - 1], previous, self.knots[i]);
+            previous = std::mem::replace(&mut self.knots[i], moved);
+        }
+
+        for (visited, knot) in self.visited.iter_mut().zip(&self.knots) {
+            if let Some(visited) = visited {
+                visited.insert(*knot);
+            }
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            for (trace, knot) in trace.iter_mut().zip(&self.knots) {
+                trace.push(*knot);
+            }
+        }
+    }
+
+    pub fn apply(&mut self, Move { direction, steps }: &Move) {
         for _ in 0..*steps {
-            match direction {
-                Direction::Up => knots[0].1 -= 1,
-                Direction::Down => knots[0].1 += 1,
-                Direction::Left => knots[0].0 -= 1,
-                Direction::Right => knots[0].0 += 1,
+            self.step(direction);
+        }
+    }
+
+    fn knot_label(&self, knot: usize) -> char {
+        match knot {
+            0 => 'H',
+            _ if self.knots.len() == 2 => 'T',
+            n => char::from_digit(n as u32, 36).unwrap_or('?'),
+        }
+    }
+
+    fn draw<F>(bounds: Bounds, cell: F) -> String
+    where
+        F: Fn(Position) -> char,
+    {
+        let ((min_x, min_y), (max_x, max_y)) = bounds;
+        let mut rendered = String::new();
+        for y in min_y..=max_y {
+            rendered.extend((min_x..=max_x).map(|x| cell((x, y))));
+            rendered.push('\n');
+        }
+        rendered
+    }
+
+    // Current state as in the puzzle text. Earlier knots cover later ones,
+    // and every knot covers the start.
+    pub fn render(&self, bounds: Bounds) -> String {
+        Self::draw(bounds, |position| {
+            match self.knots.iter().position(|&knot| knot == position) {
+                Some(knot) => self.knot_label(knot),
+                None if position == (0, 0) => 's',
+                None => '.',
             }
+        })
+    }
 
-            for i in 1..knots.len() {
-                knots[i] = new_tail_position(&knots[i - 1], &knots[i]);
+    pub fn render_visited(&self, knot: usize, bounds: Bounds) -> String {
+        Self::draw(bounds, |position| {
+            if position == (0, 0) {
+                's'
+            } else if self.has_visited(knot, position) {
+                '#'
+            } else {
+                '.'
+            }
+        })
+    }
+}
+
+// Writes the rope after every move, or after every single step, framed the
+// same way as the puzzle examples. Every frame shares the same bounds.
+pub fn animate(
+    moves: &[Move],
+    length: usize,
+    every_step: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let bounds = head_bounds(moves);
+    let mut rope = Rope::bounded(length, bounds);
+    writeln!(out, "== Initial State ==\n\n{}", rope.render(bounds))?;
+    for Move { direction, steps } in moves {
+        writeln!(out, "== {direction} {steps} ==\n")?;
+        for _ in 0..*steps {
+            rope.step(direction);
+            if every_step {
+                writeln!(out, "{}", rope.render(bounds))?;
             }
-            tail_positions.push(*knots.last().unwrap());
+        }
+        if !every_step {
+            writeln!(out, "{}", rope.render(bounds))?;
         }
     }
-    tail_positions.iter().unique().count()
+    Ok(())
+}
+
+fn tail_coverage(moves: &[Move], length: usize) -> usize {
+    let mut rope = Rope::for_moves(length, moves).tracking_only(&[length - 1]);
+    for m in moves {
+        rope.apply(m);
+    }
+    rope.visited(length - 1)
+}
+
+#[aoc(day09, part1)]
+fn solve_part1(moves: &[Move]) -> usize {
+    tail_coverage(moves, 2)
+}
+
+#[aoc(day09, part2)]
+fn solve_part2(moves: &[Move]) -> usize {
+    tail_coverage(moves, 10)
 }
 
 #[cfg(test)]
@@ -103,4 +479,109 @@ mod tests {
     fn part2() {
         assert_eq!(solve_part2(&input_generator(INPUT2)), 36);
     }
+
+    #[test]
+    fn rope() {
+        let mut rope = Rope::new(10).with_trace();
+        for m in input_generator(INPUT1) {
+            rope.apply(&m);
+        }
+        assert_eq!(rope.head(), (2, -2));
+        assert_eq!(rope.knots()[1], (1, -2));
+        assert_eq!(rope.visited(1), 13);
+        assert_eq!(rope.visited(9), 1);
+        assert_eq!(rope.trace(0).map(<[_]>::len), Some(25));
+    }
+
+    #[test]
+    fn bitmap_matches_set() {
+        let moves = input_generator(INPUT2);
+        let mut bitmap = Rope::for_moves(10, &moves);
+        let mut set = Rope::new(10);
+        for m in &moves {
+            bitmap.apply(m);
+            set.apply(m);
+        }
+        assert_eq!(bitmap.bounds(), ((-11, -15), (14, 5)));
+        assert_eq!(bitmap.bounds(), set.bounds());
+        for knot in 0..10 {
+            assert_eq!(bitmap.visited(knot), set.visited(knot));
+        }
+        let ((min_x, min_y), (max_x, max_y)) = bitmap.bounds();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                assert_eq!(bitmap.has_visited(9, (x, y)), set.has_visited(9, (x, y)));
+            }
+        }
+        assert!(!bitmap.has_visited(9, (100, 0)));
+    }
+
+    #[test]
+    fn sparse_moves() {
+        // A bitmap over this bounding box would need 400 million cells
+        let moves = input_generator("R 20000\nU 20000\nL 20000\nD 20000");
+        let rope = Rope::for_moves(2, &moves);
+        assert!(rope
+            .visited
+            .iter()
+            .flatten()
+            .all(|v| matches!(v, VisitedCells::Set(_))));
+        let mut set = Rope::new(2);
+        moves.iter().for_each(|m| set.apply(m));
+        assert_eq!(solve_part1(&moves), set.visited(1));
+        assert_eq!(set.visited(1), 79997);
+    }
+
+    #[test]
+    fn follow_rules() {
+        let moves = input_generator(INPUT1);
+        let simulate = |rope: Rope| {
+            let mut rope = rope;
+            moves.iter().for_each(|m| rope.apply(m));
+            rope
+        };
+
+        let lagged = simulate(Rope::for_moves(2, &moves).with_rule(LaggedFollow));
+        assert_eq!(lagged.visited(1), 13);
+
+        let orthogonal = simulate(Rope::for_moves(2, &moves).with_rule(OrthogonalFollow));
+        assert_eq!(orthogonal.tail(), (2, -3));
+        assert_eq!(orthogonal.visited(1), 13);
+    }
+
+    #[test]
+    fn diagonal_moves() {
+        let moves = input_generator("UR 3\nDL 1\nDR 2");
+        let mut rope = Rope::for_moves(3, &moves);
+        moves.iter().for_each(|m| rope.apply(m));
+        assert_eq!(rope.head(), (4, 0));
+        assert_eq!(rope.knots(), [(4, 0), (3, -1), (2, -1)]);
+        assert_eq!(rope.bounds(), ((0, -3), (4, 0)));
+    }
+
+    #[test]
+    fn render() {
+        let moves = input_generator(INPUT1);
+        let mut rope = Rope::new(2);
+        for m in &moves {
+            rope.apply(m);
+        }
+        let bounds = rope.bounds();
+        assert_eq!(bounds, ((0, -4), (5, 0)));
+        assert_eq!(head_bounds(&moves), bounds);
+        assert_eq!(
+            rope.render(bounds),
+            "......\n......\n.TH...\n......\ns.....\n"
+        );
+        assert_eq!(
+            rope.render_visited(1, bounds),
+            "..##..\n...##.\n.####.\n....#.\ns###..\n"
+        );
+
+        let mut frames = Vec::new();
+        animate(&input_generator("R 4\nU 1"), 10, false, &mut frames).unwrap();
+        let frames = String::from_utf8(frames).unwrap();
+        assert!(frames.contains("== R 4 ==\n\n.....\n4321H\n"));
+        assert!(frames.ends_with("== U 1 ==\n\n....H\n4321.\n\n"));
+    }
 }
@@ -1,135 +1,695 @@
-use std::str::FromStr;
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    str::FromStr,
+};
 
-pub enum Instruction {
-    Noop,
-    AddX(isize),
+use grid::Grid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub x: isize,
+    pub y: isize,
+    pub ip: usize,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers { x: 1, y: 0, ip: 0 }
+    }
+}
+
+#[derive(Debug)]
+pub struct Operation {
+    pub mnemonic: &'static str,
+    pub cycles: usize,
+    pub takes_argument: bool,
+    // Applied at the end of the instruction's last cycle. The instruction
+    // pointer has already been moved on to the next instruction.
+    pub execute: fn(&mut Registers, isize),
+    // Whether the instruction makes sense at the given index of a program of
+    // the given length
+    pub check: fn(usize, isize, usize) -> Result<(), String>,
+}
+
+fn any_position(_: usize, _: isize, _: usize) -> Result<(), String> {
+    Ok(())
+}
+
+fn jump_target(ip: usize, arg: isize) -> Option<usize> {
+    ip.checked_add_signed(arg)
+}
+
+pub const INSTRUCTION_SET: &[Operation] = &[
+    Operation {
+        mnemonic: "noop",
+        cycles: 1,
+        takes_argument: false,
+        execute: |_, _| {},
+        check: any_position,
+    },
+    Operation {
+        mnemonic: "addx",
+        cycles: 2,
+        takes_argument: true,
+        execute: |r, arg| r.x = r.x.wrapping_add(arg),
+        check: any_position,
+    },
+    Operation {
+        mnemonic: "addy",
+        cycles: 2,
+        takes_argument: true,
+        execute: |r, arg| r.y = r.y.wrapping_add(arg),
+        check: any_position,
+    },
+    Operation {
+        mnemonic: "mulx",
+        cycles: 3,
+        takes_argument: true,
+        // Loops can multiply indefinitely, so registers wrap like a real CPU's
+        execute: |r, arg| r.x = r.x.wrapping_mul(arg),
+        check: any_position,
+    },
+    // Relative to the jmp itself, so `jmp 1` does nothing. A jump off either
+    // end of the program ends it, though assemble rejects those.
+    Operation {
+        mnemonic: "jmp",
+        cycles: 2,
+        takes_argument: true,
+        execute: |r, arg| r.ip = jump_target(r.ip - 1, arg).unwrap_or(usize::MAX),
+        check: |ip, arg, len| match jump_target(ip, arg) {
+            Some(target) if target <= len => Ok(()),
+            _ => Err(format!(
+                "jump target {} is outside the program",
+                ip as i128 + arg as i128
+            )),
+        },
+    },
+];
+
+pub type Opcode = usize;
+
+fn opcode(mnemonic: &str) -> Option<Opcode> {
+    INSTRUCTION_SET
+        .iter()
+        .position(|operation| operation.mnemonic == mnemonic)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub arg: isize,
+}
+
+impl Instruction {
+    pub fn new(mnemonic: &str, arg: isize) -> Result<Instruction, String> {
+        let opcode = opcode(mnemonic).ok_or_else(|| format!("invalid instruction: {mnemonic}"))?;
+        Ok(Instruction { opcode, arg })
+    }
+
+    pub fn noop() -> Instruction {
+        Instruction::new("noop", 0).unwrap()
+    }
+
+    pub fn addx(arg: isize) -> Instruction {
+        Instruction::new("addx", arg).unwrap()
+    }
+
+    pub fn operation(&self) -> &'static Operation {
+        &INSTRUCTION_SET[self.opcode]
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.operation().cycles
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operation = self.operation();
+        if operation.takes_argument {
+            write!(f, "{} {}", operation.mnemonic, self.arg)
+        } else {
+            write!(f, "{}", operation.mnemonic)
+        }
+    }
 }
 
 impl FromStr for Instruction {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split_at(4) {
-            ("noop", "") => Ok(Self::Noop),
-            ("addx", arg) => Ok(Self::AddX(
-                arg.trim()
+        let mut words = s.split_whitespace();
+        let mnemonic = words.next().ok_or("empty instruction")?;
+        let mut instruction = Instruction::new(mnemonic, 0)?;
+        match (instruction.operation().takes_argument, words.next()) {
+            (true, Some(arg)) => {
+                instruction.arg = arg
                     .parse()
-                    .map_err(|_| format!("couldn't parse number: {arg}"))?,
-            )),
-            _ => Err(format!("invalid instruction: {s}")),
+                    .map_err(|_| format!("couldn't parse number: {arg}"))?
+            }
+            (true, None) => return Err(format!("missing argument: {s}")),
+            (false, Some(_)) => return Err(format!("unexpected argument: {s}")),
+            (false, None) => {}
         }
+        if words.next().is_some() {
+            return Err(format!("too many arguments: {s}"));
+        }
+        Ok(instruction)
     }
 }
 
-impl Instruction {
-    fn cycles(&self) -> usize {
-        match self {
-            Instruction::Noop => 1,
-            Instruction::AddX(_) => 2,
-        }
+// One instruction per line. Blank lines and anything after a `;` are ignored.
+pub fn assemble(source: &str) -> Result<Vec<Instruction>, String> {
+    let lines: Vec<_> = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split(';').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    let program = lines
+        .iter()
+        .map(|(number, line)| line.parse().map_err(|e| format!("line {number}: {e}")))
+        .collect::<Result<Vec<Instruction>, String>>()?;
+
+    for (ip, (instruction, (number, _))) in program.iter().zip(&lines).enumerate() {
+        (instruction.operation().check)(ip, instruction.arg, program.len())
+            .map_err(|e| format!("line {number}: {e}"))?;
     }
+    Ok(program)
 }
 
-struct Cpu<'a> {
-    pub x: isize,
+pub fn disassemble(program: &[Instruction]) -> String {
+    program
+        .iter()
+        .map(|instruction| format!("{instruction}\n"))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub cycle: usize,
+    pub instruction_pointer: usize,
+    pub x_before: isize,
+    pub x_after: isize,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycle {:>3}  ip {:>3}  x {} -> {}",
+            self.cycle, self.instruction_pointer, self.x_before, self.x_after
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    // Stop before the given cycle starts
+    Cycle(usize),
+    // Stop before the instruction at the given index starts executing
+    Instruction(usize),
+}
+
+pub struct Cpu<'a> {
+    pub registers: Registers,
     cycle_counter: usize,
-    instruction_pointer: usize,
     program: &'a [Instruction],
-    addition_progress: u8,
+    // Cycles already spent on the current instruction
+    progress: usize,
+    breakpoints: Vec<Breakpoint>,
 }
 
-impl Cpu<'_> {
-    fn new(program: &[Instruction]) -> Cpu {
+impl<'a> Cpu<'a> {
+    pub fn new(program: &'a [Instruction]) -> Cpu<'a> {
         Cpu {
-            x: 1,
+            registers: Registers::default(),
             cycle_counter: 1,
-            instruction_pointer: 0,
             program,
-            addition_progress: 0,
+            progress: 0,
+            breakpoints: Vec::new(),
         }
     }
 
-    fn run_until(&mut self, cycle: usize) {
-        while self.cycle_counter < cycle {
-            match self.program[self.instruction_pointer] {
-                Instruction::Noop => {
-                    self.cycle_counter += 1;
-                    self.instruction_pointer += 1;
-                    self.addition_progress = 0;
-                }
-                Instruction::AddX(arg) => {
-                    self.cycle_counter += 1;
-                    self.addition_progress += 1;
-                    if self.addition_progress == 2 {
-                        self.x += arg;
-                        self.instruction_pointer += 1;
-                        self.addition_progress = 0;
-                    }
-                }
-            }
+    pub fn cycle(&self) -> usize {
+        self.cycle_counter
+    }
+
+    pub fn x(&self) -> isize {
+        self.registers.x
+    }
+
+    pub fn instruction_pointer(&self) -> usize {
+        self.registers.ip
+    }
+
+    pub fn current_instruction(&self) -> Option<&Instruction> {
+        self.program.get(self.registers.ip)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.registers.ip >= self.program.len()
+    }
+
+    pub fn run_until(&mut self, cycle: usize) {
+        while self.cycle_counter < cycle && self.tick().is_some() {}
+    }
+
+    // Runs a single cycle, or returns None if the program has ended
+    pub fn tick(&mut self) -> Option<TraceEvent> {
+        let instruction = self.program.get(self.registers.ip)?;
+        let mut event = TraceEvent {
+            cycle: self.cycle_counter,
+            instruction_pointer: self.registers.ip,
+            x_before: self.registers.x,
+            x_after: self.registers.x,
+        };
+
+        self.progress += 1;
+        let operation = instruction.operation();
+        if self.progress == operation.cycles {
+            self.registers.ip += 1;
+            (operation.execute)(&mut self.registers, instruction.arg);
+            self.progress = 0;
         }
+
+        self.cycle_counter += 1;
+        event.x_after = self.registers.x;
+        Some(event)
     }
 
-    fn tick(&mut self) {
-        self.run_until(self.cycle_counter + 1);
+    pub fn trace(&mut self) -> Vec<TraceEvent> {
+        std::iter::from_fn(|| self.tick()).collect()
     }
 
-    fn total_cycles(&self) -> usize {
-        let mut total = 0;
-        for instruction in self.program {
-            total += instruction.cycles();
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
         }
-        total
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    fn breakpoint_hit(&self) -> Option<Breakpoint> {
+        self.breakpoints
+            .iter()
+            .copied()
+            .find(|breakpoint| match breakpoint {
+                Breakpoint::Cycle(cycle) => *cycle == self.cycle_counter,
+                Breakpoint::Instruction(ip) => *ip == self.registers.ip && self.progress == 0,
+            })
+    }
+
+    // Runs at least one cycle, then until a breakpoint is hit or the program
+    // ends. Every executed cycle is passed to on_tick.
+    pub fn resume<F>(&mut self, mut on_tick: F) -> Option<Breakpoint>
+    where
+        F: FnMut(TraceEvent),
+    {
+        while let Some(event) = self.tick() {
+            on_tick(event);
+            if let Some(breakpoint) = self.breakpoint_hit() {
+                return Some(breakpoint);
+            }
+        }
+        None
     }
 }
 
-struct Crt {
+pub struct Crt {
+    sprite_width: usize,
     current_pixel: usize,
+    framebuffer: Grid<bool>,
 }
 
-impl Crt {
-    fn new() -> Crt {
-        Crt { current_pixel: 0 }
+impl Default for Crt {
+    fn default() -> Self {
+        Crt::new(40, 6, 3)
     }
+}
 
-    fn draw(&mut self, sprite: usize) -> String {
-        let mut pixel = String::new();
-        if self.current_pixel % 40 == 0 {
-            pixel += "\n";
+impl Crt {
+    pub fn new(width: usize, height: usize, sprite_width: usize) -> Crt {
+        Crt {
+            sprite_width,
+            current_pixel: 0,
+            framebuffer: Grid::init(height, width, false),
         }
-        if sprite.abs_diff(self.current_pixel % 40) <= 1 {
-            pixel += "█";
-        } else {
-            pixel += "░";
+    }
+
+    pub fn width(&self) -> usize {
+        self.framebuffer.cols()
+    }
+
+    pub fn height(&self) -> usize {
+        self.framebuffer.rows()
+    }
+
+    pub fn framebuffer(&self) -> &Grid<bool> {
+        &self.framebuffer
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.current_pixel >= self.width() * self.height()
+    }
+
+    // Draws the next pixel with the sprite centred on the given column. Even
+    // width sprites lean to the right. Returns false once the screen is full.
+    pub fn draw(&mut self, sprite: isize) -> bool {
+        if self.is_full() {
+            return false;
         }
+        let row = self.current_pixel / self.width();
+        let col = self.current_pixel % self.width();
+        let left = sprite - (self.sprite_width as isize - 1) / 2;
+        let right = left + self.sprite_width as isize - 1;
+        *self.framebuffer.get_mut(row, col).unwrap() = (left..=right).contains(&(col as isize));
         self.current_pixel += 1;
-        pixel
+        true
+    }
+
+    pub fn to_text(&self, lit: char, dark: char) -> String {
+        let rows: Vec<String> = (0..self.height())
+            .map(|row| {
+                self.framebuffer
+                    .iter_row(row)
+                    .map(|&pixel| if pixel { lit } else { dark })
+                    .collect()
+            })
+            .collect();
+        rows.join("\n")
+    }
+
+    // Plain PBM (P1), with lit pixels as black
+    pub fn write_pbm(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "P1\n{} {}", self.width(), self.height())?;
+        writeln!(out, "{}", self.to_text('1', '0'))
     }
 }
 
-struct Device<'a> {
+pub struct Device<'a> {
     cpu: Cpu<'a>,
     crt: Crt,
 }
 
 impl Device<'_> {
-    fn new(cpu: Cpu, crt: Crt) -> Device {
+    pub fn new<'a>(cpu: Cpu<'a>, crt: Crt) -> Device<'a> {
         Device { cpu, crt }
     }
 
-    fn run(&mut self) -> String {
-        let mut rendered = String::new();
-        for _ in 0..self.cpu.total_cycles() {
-            rendered += &self.crt.draw((self.cpu.x) as usize);
-            self.cpu.tick();
+    pub fn crt(&self) -> &Crt {
+        &self.crt
+    }
+
+    // Runs until the program ends or the screen is full
+    pub fn run(&mut self) -> &Crt {
+        while !self.crt.is_full() {
+            match self.cpu.tick() {
+                Some(event) => self.crt.draw(event.x_before),
+                None => break,
+            };
+        }
+        &self.crt
+    }
+}
+
+// The capital letters of the 4x6 font used by AoC puzzles, drawn row by row
+const GLYPHS: [(char, &str); 16] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OcrError {
+    // The font is 6 pixels high, so no other screen height can be read
+    Height(usize),
+    Unrecognised {
+        // Everything that could be read, with a `?` for each unknown glyph
+        partial: String,
+        // Indices of the unrecognised glyphs, counting from the left
+        glyphs: Vec<usize>,
+    },
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OcrError::Height(rows) => write!(f, "screen is {rows} pixels high, not 6"),
+            OcrError::Unrecognised { partial, glyphs } => {
+                write!(f, "unrecognised glyphs {glyphs:?} in {partial:?}")
+            }
+        }
+    }
+}
+
+// Reads the letters off a 6 pixel high screen. Each letter is 4 pixels wide,
+// with a blank column between letters. Blank glyphs are read as spaces.
+pub fn ocr(screen: &Grid<bool>) -> Result<String, OcrError> {
+    if screen.rows() != 6 {
+        return Err(OcrError::Height(screen.rows()));
+    }
+
+    let mut text = String::new();
+    let mut unrecognised = Vec::new();
+    for (i, left) in (0..screen.cols()).step_by(5).enumerate() {
+        let glyph: String = (0..6)
+            .flat_map(|row| (left..left + 4).map(move |col| (row, col)))
+            .map(|(row, col)| match screen.get(row, col) {
+                Some(true) => '#',
+                _ => '.',
+            })
+            .collect();
+        if !glyph.contains('#') {
+            text.push(' ');
+        } else if let Some((letter, _)) = GLYPHS.iter().find(|(_, pixels)| *pixels == glyph) {
+            text.push(*letter);
+        } else {
+            text.push('?');
+            unrecognised.push(i);
+        }
+    }
+
+    let text = text.trim_end().to_string();
+    if unrecognised.is_empty() {
+        Ok(text)
+    } else {
+        Err(OcrError::Unrecognised {
+            partial: text,
+            glyphs: unrecognised,
+        })
+    }
+}
+
+// Draws text in the same font ocr reads, on a screen of the given size
+pub fn render_text(text: &str, width: usize) -> Result<Grid<bool>, String> {
+    let mut screen = Grid::init(6, width, false);
+    for (i, letter) in text.chars().enumerate() {
+        if i * 5 + 4 > width {
+            return Err(format!("{text} doesn't fit in {width} columns"));
+        }
+        if letter == ' ' {
+            continue;
+        }
+        let (_, pixels) = GLYPHS
+            .iter()
+            .find(|(glyph, _)| *glyph == letter)
+            .ok_or_else(|| format!("no glyph for {letter}"))?;
+        for (j, pixel) in pixels.chars().enumerate() {
+            *screen.get_mut(j / 4, i * 5 + j % 4).unwrap() = pixel == '#';
+        }
+    }
+    Ok(screen)
+}
+
+// Finds the shortest program of noop and addx instructions that makes a CRT
+// with a 3 pixel sprite draw the target. Every pixel is drawn with the X the
+// CPU has during that cycle, and X can only change after an addx, so this is
+// a shortest path over (pixel, X) states.
+pub fn synthesise(target: &Grid<bool>) -> Result<Vec<Instruction>, String> {
+    let width = target.cols() as isize;
+    let pixels = target.rows() * target.cols();
+    // X = -2 lights nothing on screen, and -1 and width light only the first
+    // and last columns, so no X outside -2..=width can draw anything new
+    let xs = -2..=width;
+    let index = |x: isize| (x + 2) as usize;
+    let draws = |pixel: usize, x: isize| {
+        let (row, col) = (pixel / target.cols(), pixel % target.cols());
+        pixel >= pixels || (x.abs_diff(col as isize) <= 1) == *target.get(row, col).unwrap()
+    };
+
+    // best[pixel][x] = (instruction count, previous state, instruction)
+    type State = Option<(usize, (usize, isize), Instruction)>;
+    let mut best: Vec<Vec<State>> = vec![vec![None; xs.clone().count()]; pixels + 2];
+    best[0][index(1)] = Some((0, (0, 1), Instruction::noop()));
+
+    for pixel in 0..pixels {
+        for x in xs.clone() {
+            let Some((count, _, _)) = best[pixel][index(x)] else {
+                continue;
+            };
+            let mut relax = |next: usize, next_x: isize, instruction: Instruction| {
+                let state = &mut best[next][index(next_x)];
+                if state.is_none_or(|(best_count, _, _)| count + 1 < best_count) {
+                    *state = Some((count + 1, (pixel, x), instruction));
+                }
+            };
+            if !draws(pixel, x) {
+                continue;
+            }
+            relax(pixel + 1, x, Instruction::noop());
+            if draws(pixel + 1, x) {
+                for next_x in xs.clone() {
+                    relax(pixel + 2, next_x, Instruction::addx(next_x - x));
+                }
+            }
+        }
+    }
+
+    // The program can also stop early, leaving the rest of the screen dark
+    let mut lit_after = vec![false; pixels + 2];
+    for pixel in (0..pixels).rev() {
+        lit_after[pixel] = lit_after[pixel + 1]
+            || *target
+                .get(pixel / target.cols(), pixel % target.cols())
+                .unwrap();
+    }
+    let end = (0..pixels + 2)
+        .filter(|&pixel| !lit_after[pixel])
+        .flat_map(|pixel| xs.clone().map(move |x| (pixel, x)))
+        .filter_map(|(pixel, x)| best[pixel][index(x)].map(|(count, _, _)| (count, pixel, x)))
+        .min()
+        .ok_or("target can't be drawn")?;
+
+    let mut program = Vec::new();
+    let (_, mut pixel, mut x) = end;
+    while pixel > 0 {
+        let (_, previous, instruction) = best[pixel][index(x)].unwrap();
+        program.push(instruction);
+        (pixel, x) = previous;
+    }
+    program.reverse();
+
+    let mut device = Device::new(
+        Cpu::new(&program),
+        Crt::new(target.cols(), target.rows(), 3),
+    );
+    if device.run().framebuffer() != target {
+        return Err(String::from("synthesised program draws the wrong picture"));
+    }
+    Ok(program)
+}
+
+pub fn synthesise_text(text: &str) -> Result<Vec<Instruction>, String> {
+    synthesise(&render_text(text, 40)?)
+}
+
+fn print_state(cpu: &Cpu, output: &mut dyn Write) -> io::Result<()> {
+    match cpu.current_instruction() {
+        Some(instruction) => writeln!(
+            output,
+            "cycle {}  ip {}  x {}  next: {instruction}",
+            cpu.cycle(),
+            cpu.instruction_pointer(),
+            cpu.x()
+        ),
+        None => writeln!(output, "program finished, x {}", cpu.x()),
+    }
+}
+
+// Interactive step/continue debugger reading one command per line
+pub fn debug(
+    program: &[Instruction],
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    const HELP: &str = "\
+commands:
+  s, step [n]       run n cycles (default 1)
+  c, continue       run until a breakpoint or the end
+  b cycle <n>       break before cycle n
+  b ip <n>          break before instruction n
+  d, delete         remove all breakpoints
+  p, print          show the cpu state
+  q, quit";
+
+    let mut cpu = Cpu::new(program);
+    print_state(&cpu, output)?;
+    let mut line = String::new();
+    loop {
+        write!(output, "(dbg) ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["s" | "step", rest @ ..] => {
+                let count = match rest {
+                    [] => Some(1),
+                    [n] => n.parse().ok(),
+                    _ => None,
+                };
+                let Some(count) = count else {
+                    writeln!(output, "usage: step [n]")?;
+                    continue;
+                };
+                for _ in 0..count {
+                    match cpu.tick() {
+                        Some(event) => writeln!(output, "{event}")?,
+                        None => break,
+                    }
+                }
+                print_state(&cpu, output)?;
+            }
+            ["c" | "continue"] => {
+                let mut result = Ok(());
+                let hit = cpu.resume(|event| {
+                    if result.is_ok() {
+                        result = writeln!(output, "{event}");
+                    }
+                });
+                result?;
+                if let Some(breakpoint) = hit {
+                    writeln!(output, "hit breakpoint {breakpoint:?}")?;
+                }
+                print_state(&cpu, output)?;
+            }
+            ["b" | "break", kind, n] => match (*kind, n.parse()) {
+                ("cycle", Ok(n)) => cpu.add_breakpoint(Breakpoint::Cycle(n)),
+                ("ip", Ok(n)) => cpu.add_breakpoint(Breakpoint::Instruction(n)),
+                _ => writeln!(output, "usage: b cycle <n> | b ip <n>")?,
+            },
+            ["d" | "delete"] => cpu.clear_breakpoints(),
+            ["p" | "print"] => print_state(&cpu, output)?,
+            ["q" | "quit"] => return Ok(()),
+            [] => {}
+            _ => writeln!(output, "{HELP}")?,
         }
-        rendered
     }
 }
 
 #[aoc_generator(day10)]
 pub fn input_generator(input: &str) -> Vec<Instruction> {
-    input.lines().map(|line| line.parse().unwrap()).collect()
+    assemble(input).unwrap_or_else(|e| panic!("invalid program: {e}"))
 }
 
 #[aoc(day10, part1)]
@@ -138,7 +698,7 @@ pub fn solve_part1(program: &[Instruction]) -> isize {
     let mut signal_sum = 0;
     for i in (20..=220).step_by(40) {
         cpu.run_until(i);
-        signal_sum += cpu.x * i as isize;
+        signal_sum += cpu.x() * i as isize;
     }
     signal_sum
 }
@@ -146,14 +706,17 @@ pub fn solve_part1(program: &[Instruction]) -> isize {
 #[aoc(day10, part2)]
 pub fn solve_part2(program: &[Instruction]) -> String {
     let cpu = Cpu::new(program);
-    let crt = Crt::new();
+    let crt = Crt::default();
     let mut device = Device::new(cpu, crt);
-    device.run()
+    let crt = device.run();
+    // Fall back to the picture for anything that isn't made up of letters
+    ocr(crt.framebuffer()).unwrap_or_else(|_| format!("\n{}", crt.to_text('█', '░')))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use grid::grid;
 
     const INPUT: &str = include_str!("../examples/day10/example.txt");
 
@@ -164,7 +727,195 @@ mod tests {
 
     #[test]
     fn part2() {
-        println!("{}", solve_part2(&input_generator(INPUT)));
-        // panic!();
+        let rendered = solve_part2(&input_generator(INPUT));
+        assert_eq!(rendered.lines().count(), 7);
+        assert!(rendered.starts_with("\n██░░██░░██░░"));
+    }
+
+    fn screen(text: &[&str]) -> Grid<bool> {
+        let mut screen = grid![];
+        for line in text {
+            screen.push_row(line.chars().map(|c| c == '#').collect());
+        }
+        screen
+    }
+
+    #[test]
+    fn ocr() {
+        let letters = screen(&[
+            "#..#.###..####........##",
+            "#..#.#..#.#............#",
+            "####.###..###..........#",
+            "#..#.#..#.#............#",
+            "#..#.#..#.#.........#..#",
+            "#..#.###..####.......##.",
+        ]);
+        assert_eq!(super::ocr(&letters), Ok(String::from("HBE J")));
+
+        let program = input_generator(INPUT);
+        let mut device = Device::new(Cpu::new(&program), Crt::default());
+        assert_eq!(
+            super::ocr(device.run().framebuffer()),
+            Err(OcrError::Unrecognised {
+                partial: String::from("????????"),
+                glyphs: (0..8).collect(),
+            })
+        );
+        let short = Grid::init(5, 40, false);
+        assert_eq!(super::ocr(&short), Err(OcrError::Height(5)));
+    }
+
+    #[test]
+    fn trace() {
+        let program = input_generator("noop\naddx 3\naddx -5");
+        let events = Cpu::new(&program).trace();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[2],
+            TraceEvent {
+                cycle: 3,
+                instruction_pointer: 1,
+                x_before: 1,
+                x_after: 4
+            }
+        );
+        assert_eq!(events[4].x_after, -1);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let program = input_generator(INPUT);
+        let mut cpu = Cpu::new(&program);
+        cpu.add_breakpoint(Breakpoint::Cycle(20));
+        cpu.add_breakpoint(Breakpoint::Instruction(100));
+        let mut ticks = 0;
+        assert_eq!(cpu.resume(|_| ticks += 1), Some(Breakpoint::Cycle(20)));
+        assert_eq!((ticks, cpu.cycle(), cpu.x()), (19, 20, 21));
+        assert_eq!(cpu.resume(|_| {}), Some(Breakpoint::Instruction(100)));
+        assert_eq!(cpu.instruction_pointer(), 100);
+        assert_eq!(cpu.resume(|_| {}), None);
+        assert!(cpu.is_finished());
+    }
+
+    #[test]
+    fn extended_instructions() {
+        let source = "addx 4\nmulx 3  ; x = 15\n\njmp 2\naddx 100\naddy -2\njmp -4\n";
+        let program = assemble(source).unwrap();
+        assert_eq!(program[0], Instruction::addx(4));
+        assert_eq!(program[2].cycles(), 2);
+
+        let mut cpu = Cpu::new(&program);
+        // The final jmp loops back to the mulx, skipping the addx 100
+        cpu.run_until(12);
+        let registers = Registers {
+            x: 15,
+            y: -2,
+            ip: 1,
+        };
+        assert_eq!(cpu.registers, registers);
+        cpu.run_until(15);
+        assert_eq!(
+            cpu.registers,
+            Registers {
+                x: 45,
+                ip: 2,
+                ..registers
+            }
+        );
+
+        assert!(assemble("addx").unwrap_err().contains("missing argument"));
+        assert!(assemble("noop\nfoo 1").unwrap_err().starts_with("line 2"));
+        assert_eq!(
+            assemble("noop\n\njmp -5").unwrap_err(),
+            "line 3: jump target -4 is outside the program"
+        );
+        assert!(assemble("jmp 2").is_err());
+        assert_eq!(
+            assemble(&format!("noop\njmp {}", isize::MIN)).unwrap_err(),
+            format!(
+                "line 2: jump target {} is outside the program",
+                isize::MIN as i128 + 1
+            )
+        );
+        let escape = [Instruction::new("jmp", isize::MIN).unwrap()];
+        let mut cpu = Cpu::new(&escape);
+        cpu.run_until(10);
+        assert!(cpu.is_finished());
+        assert_eq!(assemble("noop\njmp 1").unwrap().len(), 2);
+
+        // Doubling forever wraps around to zero instead of overflowing
+        let program = assemble("addx 1\nmulx 2\njmp -1").unwrap();
+        let mut cpu = Cpu::new(&program);
+        cpu.run_until(1000);
+        assert_eq!(cpu.x(), 0);
+    }
+
+    #[test]
+    fn disassemble_round_trip() {
+        let program = input_generator(INPUT);
+        let source = disassemble(&program);
+        assert_eq!(source.lines().count(), program.len());
+        assert_eq!(assemble(&source).unwrap(), program);
+        assert_eq!(source, INPUT);
+    }
+
+    #[test]
+    fn crt_geometry() {
+        let program = input_generator(INPUT);
+
+        let mut device = Device::new(Cpu::new(&program), Crt::new(10, 2, 3));
+        let crt = device.run();
+        assert_eq!(crt.to_text('#', '.'), "##..##..##\n....##....");
+        assert!(crt.is_full());
+
+        let mut device = Device::new(Cpu::new(&program), Crt::new(40, 1, 1));
+        assert_eq!(
+            device.run().to_text('#', '.'),
+            ".#...#..#...#....#......................"
+        );
+
+        let mut pbm = Vec::new();
+        Crt::new(3, 2, 3).write_pbm(&mut pbm).unwrap();
+        assert_eq!(String::from_utf8(pbm).unwrap(), "P1\n3 2\n000\n000\n");
+    }
+
+    #[test]
+    fn synthesise() {
+        let program = synthesise_text("ZEBRA").unwrap();
+        let mut device = Device::new(Cpu::new(&program), Crt::default());
+        assert_eq!(
+            super::ocr(device.run().framebuffer()),
+            Ok(String::from("ZEBRA"))
+        );
+
+        let example = input_generator(INPUT);
+        let mut device = Device::new(Cpu::new(&example), Crt::default());
+        let target = device.run().framebuffer().clone();
+        let program = super::synthesise(&target).unwrap();
+        assert!(program.len() <= example.len());
+
+        let blank = Grid::init(6, 40, false);
+        assert_eq!(super::synthesise(&blank), Ok(Vec::new()));
+        // A single addx draws the two pixels that are always lit and stops
+        let mut corner = blank.clone();
+        *corner.get_mut(0, 0).unwrap() = true;
+        *corner.get_mut(0, 1).unwrap() = true;
+        assert_eq!(super::synthesise(&corner).unwrap().len(), 1);
+
+        // X starts at 1 and can't change before the third cycle, so the first
+        // two pixels are always lit
+        assert!(synthesise_text("HELLO").is_err());
+        assert!(synthesise_text("QQ").is_err());
+    }
+
+    #[test]
+    fn debugger() {
+        let program = input_generator("noop\naddx 3\naddx -5");
+        let mut output = Vec::new();
+        debug(&program, &mut "b ip 2\nc\ns 2\nq\n".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("hit breakpoint Instruction(2)"));
+        assert!(output.contains("cycle   5  ip   2  x 4 -> -1"));
+        assert!(output.contains("program finished, x -1"));
     }
 }
@@ -1,70 +1,456 @@
-use std::str::FromStr;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt,
+    ops::{Add, Div, Mul, Rem},
+    thread,
+};
 
 use nom::{
+    branch::alt,
     bytes::complete::tag,
-    character::complete::{anychar, newline, not_line_ending, u64},
-    multi::{many0, many_till},
-    sequence, IResult,
+    character::complete::{line_ending, multispace0, one_of, space0, u32, u64},
+    combinator::{all_consuming, cut, map, map_opt, not, opt, value},
+    error::{context, convert_error, VerboseError},
+    multi::{many1, separated_list0},
+    sequence::{delimited, preceded, terminated, tuple},
+    Finish,
 };
 
 type MonkeyId = usize;
 type ModularStressLevel = usize;
 
-enum Operand {
-    Number(ModularStressLevel),
-    Old,
+// Arbitrary precision unsigned integer as little endian 32 bit limbs, with no
+// zero limbs at the top
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn trim(mut self) -> Self {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    fn to_u128(&self) -> Option<u128> {
+        (self.0.len() <= 4).then(|| {
+            self.0
+                .iter()
+                .rev()
+                .fold(0, |n, &limb| n << 32 | limb as u128)
+        })
+    }
+
+    fn bits(&self) -> usize {
+        self.0
+            .last()
+            .map_or(0, |top| self.0.len() * 32 - top.leading_zeros() as usize)
+    }
+
+    fn checked_sub(&self, other: &BigUint) -> Option<BigUint> {
+        if *self < *other {
+            return None;
+        }
+        let mut borrow = 0;
+        let mut limbs = Vec::with_capacity(self.0.len());
+        for (i, &limb) in self.0.iter().enumerate() {
+            let rhs = other.0.get(i).copied().unwrap_or(0) as i64 + borrow;
+            let mut difference = limb as i64 - rhs;
+            borrow = 0;
+            if difference < 0 {
+                difference += 1 << 32;
+                borrow = 1;
+            }
+            limbs.push(difference as u32);
+        }
+        Some(BigUint(limbs).trim())
+    }
+
+    // Quotient and remainder for a divisor that fits in 64 bits
+    fn div_rem_small(&self, divisor: u64) -> (BigUint, u64) {
+        let mut quotient = vec![0; self.0.len()];
+        let mut remainder = 0u128;
+        for (i, &limb) in self.0.iter().enumerate().rev() {
+            let n = remainder << 32 | limb as u128;
+            quotient[i] = (n / divisor as u128) as u32;
+            remainder = n % divisor as u128;
+        }
+        (BigUint(quotient).trim(), remainder as u64)
+    }
+
+    // Binary long division, only needed when the divisor itself is huge
+    fn div_rem(&self, divisor: &BigUint) -> (BigUint, BigUint) {
+        assert!(!divisor.0.is_empty(), "division by zero");
+        let mut quotient = vec![0; self.0.len()];
+        let mut remainder = BigUint::default();
+        for i in (0..self.bits()).rev() {
+            // remainder = 2 * remainder + the next bit down
+            let mut carry = self.0[i / 32] >> (i % 32) & 1;
+            for limb in remainder.0.iter_mut() {
+                let next = *limb >> 31;
+                *limb = *limb << 1 | carry;
+                carry = next;
+            }
+            if carry != 0 {
+                remainder.0.push(carry);
+            }
+            if remainder >= *divisor {
+                remainder = remainder.checked_sub(divisor).unwrap();
+                quotient[i / 32] |= 1 << (i % 32);
+            }
+        }
+        (BigUint(quotient).trim(), remainder)
+    }
+}
+
+impl From<u128> for BigUint {
+    fn from(mut n: u128) -> Self {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push(n as u32);
+            n >>= 32;
+        }
+        BigUint(limbs)
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: &BigUint) -> BigUint {
+        let mut carry = 0;
+        let mut limbs = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        for i in 0..self.0.len().max(other.0.len()) {
+            let sum = self.0.get(i).copied().unwrap_or(0) as u64
+                + other.0.get(i).copied().unwrap_or(0) as u64
+                + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        BigUint(limbs).trim()
+    }
+}
+
+impl Mul for &BigUint {
+    type Output = BigUint;
+
+    fn mul(self, other: &BigUint) -> BigUint {
+        let mut limbs = vec![0u32; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.0.iter().enumerate() {
+                let product = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.0.len()] = carry as u32;
+        }
+        BigUint(limbs).trim()
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.0.is_empty() {
+            let (quotient, chunk) = n.div_rem_small(CHUNK);
+            chunks.push(chunk);
+            n = quotient;
+        }
+        match chunks.split_last() {
+            None => write!(f, "0"),
+            Some((top, rest)) => {
+                write!(f, "{top}")?;
+                rest.iter()
+                    .rev()
+                    .try_for_each(|chunk| write!(f, "{chunk:019}"))
+            }
+        }
+    }
+}
+
+// A worry level, which stays machine sized until an operation would overflow
+// and then carries on with arbitrary precision. Big only ever holds values
+// that don't fit in Small, so equal levels always compare and hash equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Worry {
+    Small(ModularStressLevel),
+    Big(BigUint),
+}
+
+impl Worry {
+    fn big(&self) -> BigUint {
+        match self {
+            Worry::Small(n) => BigUint::from(*n as u128),
+            Worry::Big(n) => n.clone(),
+        }
+    }
+
+    pub fn checked_sub(&self, other: &Worry) -> Option<Worry> {
+        match (self, other) {
+            (Worry::Small(a), Worry::Small(b)) => a.checked_sub(*b).map(Worry::Small),
+            _ => self.big().checked_sub(&other.big()).map(Worry::from),
+        }
+    }
+
+    pub fn rem_small(&self, divisor: ModularStressLevel) -> ModularStressLevel {
+        match self {
+            Worry::Small(n) => n % divisor,
+            Worry::Big(n) => n.div_rem_small(divisor as u64).1 as ModularStressLevel,
+        }
+    }
+
+    pub fn is_multiple_of(&self, divisor: ModularStressLevel) -> bool {
+        self.rem_small(divisor) == 0
+    }
+
+    pub fn pow(&self, mut exponent: u32, modulus: Option<&Worry>) -> Worry {
+        let reduce = |n: Worry| modulus.map_or_else(|| n.clone(), |m| &n % m);
+        let mut base = reduce(self.clone());
+        let mut result = reduce(Worry::Small(1));
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = reduce(&result * &base);
+            }
+            base = reduce(&base * &base);
+            exponent >>= 1;
+        }
+        result
+    }
+}
+
+impl From<u128> for Worry {
+    fn from(n: u128) -> Self {
+        match ModularStressLevel::try_from(n) {
+            Ok(n) => Worry::Small(n),
+            Err(_) => Worry::Big(BigUint::from(n)),
+        }
+    }
 }
 
-impl Operand {
-    fn evaluate(&self, item: ModularStressLevel) -> ModularStressLevel {
+impl From<BigUint> for Worry {
+    fn from(n: BigUint) -> Self {
+        match n.to_u128() {
+            Some(n) => Worry::from(n),
+            None => Worry::Big(n),
+        }
+    }
+}
+
+impl Add for &Worry {
+    type Output = Worry;
+
+    fn add(self, other: &Worry) -> Worry {
+        match (self, other) {
+            (Worry::Small(a), Worry::Small(b)) => a
+                .checked_add(*b)
+                .map_or_else(|| Worry::from(*a as u128 + *b as u128), Worry::Small),
+            _ => Worry::from(&self.big() + &other.big()),
+        }
+    }
+}
+
+impl Mul for &Worry {
+    type Output = Worry;
+
+    fn mul(self, other: &Worry) -> Worry {
+        match (self, other) {
+            (Worry::Small(a), Worry::Small(b)) => a
+                .checked_mul(*b)
+                .map_or_else(|| Worry::from(*a as u128 * *b as u128), Worry::Small),
+            _ => Worry::from(&self.big() * &other.big()),
+        }
+    }
+}
+
+impl Div<ModularStressLevel> for &Worry {
+    type Output = Worry;
+
+    fn div(self, divisor: ModularStressLevel) -> Worry {
         match self {
-            Operand::Number(n) => *n,
-            Operand::Old => item,
+            Worry::Small(n) => Worry::Small(n / divisor),
+            Worry::Big(n) => Worry::from(n.div_rem_small(divisor as u64).0),
         }
     }
 }
 
-enum Operation {
-    Addition(Operand, Operand),
-    Multiplication(Operand, Operand),
+impl Rem for &Worry {
+    type Output = Worry;
+
+    fn rem(self, divisor: &Worry) -> Worry {
+        match (self, divisor) {
+            (_, Worry::Small(d)) => Worry::Small(self.rem_small(*d)),
+            (Worry::Small(_), Worry::Big(_)) => self.clone(),
+            (Worry::Big(n), Worry::Big(d)) => Worry::from(n.div_rem(d).1),
+        }
+    }
 }
 
-impl Operation {
-    fn evaluate(&self, item: ModularStressLevel) -> ModularStressLevel {
+impl fmt::Display for Worry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Operation::Addition(op1, op2) => op1.evaluate(item) + op2.evaluate(item),
-            Operation::Multiplication(op1, op2) => op1.evaluate(item) * op2.evaluate(item),
+            Worry::Small(n) => write!(f, "{n}"),
+            Worry::Big(n) => write!(f, "{n}"),
         }
     }
 }
 
-impl FromStr for Operation {
-    type Err = ();
+fn gcd(a: ModularStressLevel, b: ModularStressLevel) -> ModularStressLevel {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Old,
+    Number(ModularStressLevel),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, u32),
+}
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Old | Expr::Number(_) => 3,
+            Expr::Pow(..) => 2,
+            Expr::Mul(..) => 1,
+            Expr::Add(..) | Expr::Sub(..) => 0,
+        }
+    }
+
+    // With a modulus, everything is reduced modulo it, which is only valid as
+    // long as nothing divides the result afterwards. Without one, worry levels
+    // can't go below zero.
+    pub fn evaluate(&self, old: &Worry, modulus: Option<&Worry>) -> Result<Worry, String> {
+        let reduce = |n: Worry| modulus.map_or_else(|| n.clone(), |m| &n % m);
+        Ok(match self {
+            Expr::Old => reduce(old.clone()),
+            Expr::Number(n) => reduce(Worry::Small(*n)),
+            Expr::Add(a, b) => reduce(&a.evaluate(old, modulus)? + &b.evaluate(old, modulus)?),
+            Expr::Sub(a, b) => {
+                let (a, b) = (a.evaluate(old, modulus)?, b.evaluate(old, modulus)?);
+                match modulus {
+                    Some(m) => reduce((&a + m).checked_sub(&b).unwrap()),
+                    None => a
+                        .checked_sub(&b)
+                        .ok_or_else(|| format!("worry level went negative in {a} - {b}"))?,
+                }
+            }
+            Expr::Mul(a, b) => reduce(&a.evaluate(old, modulus)? * &b.evaluate(old, modulus)?),
+            Expr::Pow(base, exponent) => base.evaluate(old, modulus)?.pow(*exponent, modulus),
+        })
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut words = s.split_whitespace();
-        let op1 = match words.next().unwrap() {
-            "old" => Operand::Old,
-            number => Operand::Number(number.parse().unwrap()),
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Operators are left associative, so a right operand of equal
+        // precedence needs brackets
+        let operand = |f: &mut fmt::Formatter, e: &Expr, min: u8| {
+            if e.precedence() < min {
+                write!(f, "({e})")
+            } else {
+                write!(f, "{e}")
+            }
         };
-        let operation = words.next().unwrap();
-        let op2 = match words.next().unwrap() {
-            "old" => Operand::Old,
-            number => Operand::Number(number.parse().unwrap()),
+        let (a, operator, b) = match self {
+            Expr::Old => return write!(f, "old"),
+            Expr::Number(n) => return write!(f, "{n}"),
+            Expr::Pow(base, exponent) => {
+                operand(f, base, 3)?;
+                return write!(f, " ^ {exponent}");
+            }
+            Expr::Add(a, b) => (a, "+", b),
+            Expr::Sub(a, b) => (a, "-", b),
+            Expr::Mul(a, b) => (a, "*", b),
         };
+        let precedence = self.precedence();
+        operand(f, a, precedence)?;
+        write!(f, " {operator} ")?;
+        operand(f, b, precedence + 1)
+    }
+}
 
-        match operation {
-            "+" => Ok(Operation::Addition(op1, op2)),
-            "*" => Ok(Operation::Multiplication(op1, op2)),
-            _ => Err(()),
+// What happens to items during a round, worded like the puzzle's walkthrough
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Turn(MonkeyId),
+    Inspect(Worry),
+    Operation(Expr, Worry),
+    Bored(ModularStressLevel, Worry),
+    Test(ModularStressLevel, bool),
+    Throw(Worry, MonkeyId),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Turn(id) => write!(f, "Monkey {id}:"),
+            Event::Inspect(item) => {
+                write!(f, "  Monkey inspects an item with a worry level of {item}.")
+            }
+            Event::Operation(operation, result) => {
+                let change = match operation {
+                    Expr::Add(a, b) if **a == Expr::Old => match **b {
+                        Expr::Old => "increases by itself".to_string(),
+                        Expr::Number(n) => format!("increases by {n}"),
+                        _ => format!("becomes {operation}"),
+                    },
+                    Expr::Mul(a, b) if **a == Expr::Old => match **b {
+                        Expr::Old => "is multiplied by itself".to_string(),
+                        Expr::Number(n) => format!("is multiplied by {n}"),
+                        _ => format!("becomes {operation}"),
+                    },
+                    _ => format!("becomes {operation}"),
+                };
+                write!(f, "    Worry level {change} to {result}.")
+            }
+            Event::Bored(divisor, result) => write!(
+                f,
+                "    Monkey gets bored with item. Worry level is divided by {divisor} to {result}."
+            ),
+            Event::Test(modulus, divisible) => write!(
+                f,
+                "    Current worry level is {}divisible by {modulus}.",
+                if *divisible { "" } else { "not " }
+            ),
+            Event::Throw(item, target) => write!(
+                f,
+                "    Item with worry level {item} is thrown to monkey {target}."
+            ),
         }
     }
 }
 
-struct Monkey {
-    items: Vec<ModularStressLevel>,
+#[derive(Debug, Clone)]
+pub struct Monkey {
+    items: VecDeque<Worry>,
     inspected: usize,
-    operation: Operation,
+    operation: Expr,
     modulus: ModularStressLevel,
     worry_divisor: ModularStressLevel,
     true_monkey: MonkeyId,
@@ -72,99 +458,444 @@ struct Monkey {
 }
 
 impl Monkey {
-    fn inspect(&mut self) -> Option<(MonkeyId, ModularStressLevel)> {
-        let item = self.items.pop()?;
-        self.inspected += 1;
-        let item = self.operation.evaluate(item);
-        let item = item / self.worry_divisor;
-        if item % self.modulus == 0 {
-            Some((self.true_monkey, item))
+    // Where an item is thrown and with what worry level, without counting it
+    fn throw(
+        &self,
+        item: Worry,
+        modulus: &Worry,
+        mut log: Option<&mut Vec<Event>>,
+    ) -> Result<(MonkeyId, Worry), String> {
+        let mut record = |event| {
+            if let Some(log) = log.as_mut() {
+                log.push(event);
+            }
+        };
+        record(Event::Inspect(item.clone()));
+        // Reducing only commutes with the operations when nothing is divided
+        let reduce_by = (self.worry_divisor == 1).then_some(modulus);
+        let item = self.operation.evaluate(&item, reduce_by)?;
+        record(Event::Operation(self.operation.clone(), item.clone()));
+        let item = if self.worry_divisor == 1 {
+            item
         } else {
-            Some((self.false_monkey, item))
-        }
+            let relieved = &item / self.worry_divisor;
+            record(Event::Bored(self.worry_divisor, relieved.clone()));
+            relieved
+        };
+        let divisible = item.is_multiple_of(self.modulus);
+        record(Event::Test(self.modulus, divisible));
+        let target = if divisible {
+            self.true_monkey
+        } else {
+            self.false_monkey
+        };
+        record(Event::Throw(item.clone(), target));
+        Ok((target, item))
+    }
+
+    fn inspect(
+        &mut self,
+        modulus: &Worry,
+        log: Option<&mut Vec<Event>>,
+    ) -> Option<Result<(MonkeyId, Worry), String>> {
+        let item = self.items.pop_front()?;
+        self.inspected += 1;
+        Some(self.throw(item, modulus, log))
     }
 }
 
 pub struct Monkeys {
     monkeys: Vec<Monkey>,
-    modulus: ModularStressLevel,
+    // The least common multiple of every monkey's test
+    modulus: Worry,
+    rounds: usize,
+    log: Option<Vec<Event>>,
+    threads: usize,
 }
 
 impl Monkeys {
     fn new(monkeys: Vec<Monkey>) -> Monkeys {
-        let mut modulus = 1;
+        let mut modulus = Worry::Small(1);
         for monkey in &monkeys {
-            modulus *= monkey.modulus;
+            let common = gcd(monkey.modulus, modulus.rem_small(monkey.modulus));
+            modulus = &(&modulus / common) * &Worry::Small(monkey.modulus);
+        }
+        Monkeys {
+            monkeys,
+            modulus,
+            rounds: 0,
+            log: None,
+            threads: 1,
+        }
+    }
+
+    // The monkeys described by the notes, dividing worry levels by the given
+    // amount after each inspection
+    pub fn from_notes(input: &str, worry_divisor: ModularStressLevel) -> Result<Monkeys, String> {
+        if worry_divisor == 0 {
+            return Err("worry levels can't be divided by 0".to_string());
+        }
+        let mut monkeys = parse_notes(input)?;
+        for monkey in monkeys.iter_mut() {
+            monkey.worry_divisor = worry_divisor;
         }
-        Monkeys { monkeys, modulus }
+        Ok(Monkeys::new(monkeys))
+    }
+
+    // Record every step of the rounds from here on
+    pub fn with_log(mut self) -> Self {
+        self.log = Some(Vec::new());
+        self
+    }
+
+    // Spread inspection_counts over this many threads
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn events(&self) -> Option<&[Event]> {
+        self.log.as_deref()
     }
 
-    fn do_round(&mut self) {
+    pub fn narrative(&self) -> Option<String> {
+        let events = self.log.as_ref()?;
+        Some(events.iter().map(|event| format!("{event}\n")).collect())
+    }
+
+    // Play one round, with every monkey taking its turn in order
+    pub fn do_round(&mut self) -> Result<(), String> {
         for i in 0..self.monkeys.len() {
-            while let Some((id, item)) = self.monkeys[i].inspect() {
-                self.monkeys[id].items.push(item % self.modulus);
+            if let Some(log) = self.log.as_mut() {
+                log.push(Event::Turn(i));
             }
+            while let Some(thrown) = self.monkeys[i].inspect(&self.modulus, self.log.as_mut()) {
+                let (id, item) = thrown.map_err(|e| format!("monkey {i}: {e}"))?;
+                self.monkeys[id].items.push_back(item);
+            }
+        }
+        self.rounds += 1;
+        Ok(())
+    }
+
+    pub fn holdings_report(&self) -> String {
+        let mut report = format!(
+            "After round {}, the monkeys are holding items with these worry levels:\n",
+            self.rounds
+        );
+        for (id, monkey) in self.monkeys.iter().enumerate() {
+            let items: Vec<_> = monkey.items.iter().map(|item| item.to_string()).collect();
+            report += &format!("Monkey {id}: {}\n", items.join(", "));
         }
+        report
+    }
+
+    pub fn inspections_report(&self) -> String {
+        let mut report = format!("== After round {} ==\n", self.rounds);
+        for (id, monkey) in self.monkeys.iter().enumerate() {
+            report += &format!("Monkey {id} inspected items {} times.\n", monkey.inspected);
+        }
+        report
     }
 
     fn get_monkey_business(&mut self) -> usize {
+        monkey_business(self.monkeys.iter().map(|monkey| monkey.inspected).collect())
+    }
+
+    // One round of a single item's journey starting with the given monkey. Other
+    // items never affect where it goes, so it can be followed on its own: it
+    // keeps moving within the round while it is thrown to monkeys later in the
+    // order, and waits for the next round otherwise.
+    fn item_round(
+        &self,
+        (mut holder, mut item): (MonkeyId, Worry),
+        inspections: &mut [usize],
+    ) -> Result<(MonkeyId, Worry), String> {
+        loop {
+            inspections[holder] += 1;
+            let (target, worry) = self.monkeys[holder]
+                .throw(item, &self.modulus, None)
+                .map_err(|e| format!("monkey {holder}: {e}"))?;
+            item = worry;
+            if target < holder {
+                return Ok((target, item));
+            }
+            holder = target;
+        }
+    }
+
+    // How many items each monkey inspects over a single item's first `rounds`
+    // rounds. Its (monkey, worry) state at the start of a round determines
+    // everything after it, so once a state repeats the rest is extrapolated
+    // from the cycle.
+    fn item_inspections(
+        &self,
+        start: (MonkeyId, Worry),
+        rounds: usize,
+    ) -> Result<Vec<usize>, String> {
+        let mut seen: HashMap<_, usize> = HashMap::new();
+        // history[r] holds the inspection counts after r rounds
+        let mut history = vec![vec![0; self.monkeys.len()]];
+        let mut state = start;
+        for round in 0..rounds {
+            if let Some(&first) = seen.get(&state) {
+                let period = round - first;
+                let (cycles, rest) = ((rounds - round) / period, (rounds - round) % period);
+                return Ok((0..self.monkeys.len())
+                    .map(|i| {
+                        let per_cycle = history[round][i] - history[first][i];
+                        let partial = history[first + rest][i] - history[first][i];
+                        history[round][i] + cycles * per_cycle + partial
+                    })
+                    .collect());
+            }
+            seen.insert(state.clone(), round);
+            let mut inspections = history[round].clone();
+            state = self.item_round(state, &mut inspections)?;
+            history.push(inspections);
+        }
+        Ok(history.pop().unwrap())
+    }
+
+    fn items(&self) -> Vec<(MonkeyId, Worry)> {
         self.monkeys
-            .sort_by(|monkey1, monkey2| monkey1.inspected.cmp(&monkey2.inspected));
+            .iter()
+            .enumerate()
+            .flat_map(|(id, monkey)| monkey.items.iter().map(move |item| (id, item.clone())))
+            .collect()
+    }
+
+    // Inspection counts per monkey after the given number of rounds, without
+    // changing the monkeys
+    pub fn inspection_counts(&self, rounds: usize) -> Result<Vec<usize>, String> {
+        let items = self.items();
+        if self.threads == 1 || items.len() < 2 {
+            return self.count_inspections(&items, rounds);
+        }
+
+        // Every thread follows its own share of the items and the per-thread
+        // counts are summed in a fixed order
+        let share = items.len().div_ceil(self.threads);
+        let partial_counts: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = items
+                .chunks(share)
+                .map(|chunk| scope.spawn(move || self.count_inspections(chunk, rounds)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        let mut counts = vec![0; self.monkeys.len()];
+        for partial in partial_counts {
+            for (count, n) in counts.iter_mut().zip(partial?) {
+                *count += n;
+            }
+        }
+        Ok(counts)
+    }
+
+    fn count_inspections(
+        &self,
+        items: &[(MonkeyId, Worry)],
+        rounds: usize,
+    ) -> Result<Vec<usize>, String> {
+        let mut counts = vec![0; self.monkeys.len()];
+        for item in items {
+            for (count, n) in counts
+                .iter_mut()
+                .zip(self.item_inspections(item.clone(), rounds)?)
+            {
+                *count += n;
+            }
+        }
+        Ok(counts)
+    }
+}
+
+fn monkey_business(mut counts: Vec<usize>) -> usize {
+    counts.sort_unstable();
+    counts.pop().unwrap() * counts.pop().unwrap()
+}
+
+type IResult<'a, T> = nom::IResult<&'a str, T, VerboseError<&'a str>>;
+
+fn line<'a, T, F>(
+    label: &'static str,
+    prefix: &'static str,
+    parser: F,
+) -> impl FnMut(&'a str) -> IResult<'a, T>
+where
+    F: FnMut(&'a str) -> IResult<'a, T>,
+{
+    context(
+        label,
+        delimited(
+            preceded(space0, tag(prefix)),
+            cut(parser),
+            preceded(space0, line_ending),
+        ),
+    )
+}
+
+fn number(input: &str) -> IResult<'_, ModularStressLevel> {
+    map(u64, |n| n as ModularStressLevel)(input)
+}
 
-        self.monkeys.pop().unwrap().inspected * self.monkeys.pop().unwrap().inspected
+// Precedence climbing, loosest first: + and -, then *, then ^ (or **) with
+// a constant exponent
+fn expr(input: &str) -> IResult<'_, Expr> {
+    let (mut input, mut lhs) = term(input)?;
+    loop {
+        // Only a missing operator ends the expression, anything after one has
+        // to be a term
+        let (rest, (operator, rhs)) =
+            match tuple((delimited(space0, one_of("+-"), space0), cut(term)))(input) {
+                Err(nom::Err::Error(_)) => return Ok((input, lhs)),
+                result => result?,
+            };
+        lhs = match operator {
+            '+' => Expr::Add(Box::new(lhs), Box::new(rhs)),
+            _ => Expr::Sub(Box::new(lhs), Box::new(rhs)),
+        };
+        input = rest;
     }
 }
 
-fn next_number(input: &str) -> IResult<&str, ModularStressLevel> {
-    let (input, (_, number)) = many_till(anychar, u64)(input)?;
-    Ok((input, number as ModularStressLevel))
+fn term(input: &str) -> IResult<'_, Expr> {
+    let (mut input, mut lhs) = power(input)?;
+    loop {
+        let (rest, rhs) = match preceded(
+            delimited(space0, terminated(tag("*"), not(tag("*"))), space0),
+            cut(power),
+        )(input)
+        {
+            Err(nom::Err::Error(_)) => return Ok((input, lhs)),
+            result => result?,
+        };
+        lhs = Expr::Mul(Box::new(lhs), Box::new(rhs));
+        input = rest;
+    }
 }
 
-fn parse_monkey(input: &str) -> IResult<&str, Monkey> {
-    let (input, _) = next_number(input)?;
-    let (input, (items, _)) = many_till(next_number, newline)(input)?;
-    let (input, operation) =
-        sequence::preceded(many_till(anychar, tag("= ")), not_line_ending)(input)?;
-    let (input, modulus) = next_number(input)?;
-    let (input, true_monkey) = next_number(input)?;
-    let (input, false_monkey) = next_number(input)?;
+fn power_operator(input: &str) -> IResult<'_, &str> {
+    delimited(space0, alt((tag("^"), tag("**"))), space0)(input)
+}
+
+fn power(input: &str) -> IResult<'_, Expr> {
+    let (input, base) = atom(input)?;
+    let (input, exponent) = opt(preceded(power_operator, cut(exponent)))(input)?;
+    match exponent {
+        Some(exponent) => Ok((input, Expr::Pow(Box::new(base), exponent))),
+        None => Ok((input, base)),
+    }
+}
+
+// Powers are right associative, so a chain of them in an exponent is folded
+// from the right into a single constant
+fn exponent(input: &str) -> IResult<'_, u32> {
+    context(
+        "exponent",
+        map_opt(
+            tuple((u32, opt(preceded(power_operator, cut(exponent))))),
+            |(base, exponent)| match exponent {
+                Some(exponent) => base.checked_pow(exponent),
+                None => Some(base),
+            },
+        ),
+    )(input)
+}
+
+fn atom(input: &str) -> IResult<'_, Expr> {
+    context(
+        "operand",
+        alt((
+            value(Expr::Old, tag("old")),
+            map(number, Expr::Number),
+            delimited(
+                terminated(tag("("), space0),
+                cut(expr),
+                cut(preceded(space0, tag(")"))),
+            ),
+        )),
+    )(input)
+}
+
+fn parse_monkey(input: &str) -> IResult<'_, (MonkeyId, Monkey)> {
+    let (input, id) = line("monkey header", "Monkey ", terminated(number, tag(":")))(input)?;
+    let (input, items) = line(
+        "starting items",
+        "Starting items:",
+        preceded(space0, separated_list0(tuple((tag(","), space0)), number)),
+    )(input)?;
+    let (input, operation) = line("operation", "Operation: new =", preceded(space0, expr))(input)?;
+    let (input, modulus) = line("test", "Test: divisible by ", number)(input)?;
+    let (input, true_monkey) = line("true target", "If true: throw to monkey ", number)(input)?;
+    let (input, false_monkey) = line("false target", "If false: throw to monkey ", number)(input)?;
     let monkey = Monkey {
-        items,
+        items: items.into_iter().map(Worry::Small).collect(),
         inspected: 0,
-        operation: operation.parse().unwrap(),
+        operation,
         modulus,
         worry_divisor: 0,
-        true_monkey: true_monkey as MonkeyId,
-        false_monkey: false_monkey as MonkeyId,
+        true_monkey,
+        false_monkey,
     };
 
-    Ok((input, monkey))
+    Ok((input, (id, monkey)))
 }
 
-fn input_generator(input: &str, worry_divisor: ModularStressLevel) -> Monkeys {
-    let (_, mut monkeys) = many0(parse_monkey)(input).unwrap();
-    for monkey in monkeys.iter_mut() {
-        monkey.worry_divisor = worry_divisor;
+// The worry divisor is left for Monkeys::from_notes to fill in
+fn parse_notes(input: &str) -> Result<Vec<Monkey>, String> {
+    // Make sure the last line is terminated like the rest
+    let input = input.trim_end().to_string() + "\n";
+    let (_, monkeys) =
+        all_consuming(many1(delimited(multispace0, parse_monkey, multispace0)))(input.as_str())
+            .finish()
+            .map_err(|e| convert_error(input.as_str(), e))?;
+
+    for (i, (id, monkey)) in monkeys.iter().enumerate() {
+        if *id != i {
+            return Err(format!("expected monkey {i}, found monkey {id}"));
+        }
+        if monkey.modulus == 0 {
+            return Err(format!("monkey {id} tests divisibility by 0"));
+        }
+        for target in [monkey.true_monkey, monkey.false_monkey] {
+            if target >= monkeys.len() {
+                return Err(format!("monkey {id} throws to missing monkey {target}"));
+            }
+            if target == *id {
+                return Err(format!("monkey {id} throws to itself"));
+            }
+        }
     }
-    Monkeys::new(monkeys)
+
+    Ok(monkeys.into_iter().map(|(_, monkey)| monkey).collect())
+}
+
+fn input_generator(input: &str, worry_divisor: ModularStressLevel) -> Monkeys {
+    Monkeys::from_notes(input, worry_divisor).unwrap_or_else(|e| panic!("invalid notes:\n{e}"))
 }
 
 #[aoc(day11, part1)]
-pub fn solve_part1(input: &str) -> usize {
+pub fn solve_part1(input: &str) -> Result<usize, String> {
     let mut monkeys = input_generator(input, 3);
     for _ in 0..20 {
-        monkeys.do_round();
+        monkeys.do_round()?;
     }
-    monkeys.get_monkey_business()
+    Ok(monkeys.get_monkey_business())
 }
 
 #[aoc(day11, part2)]
-pub fn solve_part2(input: &str) -> usize {
-    let mut monkeys = input_generator(input, 1);
-    for _ in 0..10000 {
-        monkeys.do_round();
-    }
-    monkeys.get_monkey_business()
+pub fn solve_part2(input: &str) -> Result<usize, String> {
+    solve_part2_threaded(input, 1)
+}
+
+// Part 2 with the items shared out over several threads
+pub fn solve_part2_threaded(input: &str, threads: usize) -> Result<usize, String> {
+    let monkeys = Monkeys::from_notes(input, 1)?.with_threads(threads);
+    Ok(monkey_business(monkeys.inspection_counts(10000)?))
 }
 
 #[cfg(test)]
@@ -175,11 +906,259 @@ mod tests {
 
     #[test]
     fn part1() {
-        assert_eq!(solve_part1(INPUT), 10605);
+        assert_eq!(solve_part1(INPUT), Ok(10605));
     }
 
     #[test]
     fn part2() {
-        assert_eq!(solve_part2(INPUT), 2713310158);
+        assert_eq!(solve_part2(INPUT), Ok(2713310158));
+    }
+
+    #[test]
+    fn item_cycles() {
+        let mut monkeys = input_generator(INPUT, 1);
+        assert_eq!(monkeys.inspection_counts(20).unwrap(), [99, 97, 8, 103]);
+        assert_eq!(
+            monkeys.inspection_counts(10000).unwrap(),
+            [52166, 47830, 1938, 52013]
+        );
+        let counts = monkeys.inspection_counts(1_000_000_000_000).unwrap();
+        // Every item is inspected at least once a round
+        assert!(counts.iter().sum::<usize>() >= 10 * 1_000_000_000_000);
+
+        for _ in 0..1000 {
+            monkeys.do_round().unwrap();
+        }
+        let inspected: Vec<_> = monkeys.monkeys.iter().map(|m| m.inspected).collect();
+        assert_eq!(inspected, [5204, 4792, 199, 5192]);
+        assert_eq!(
+            inspected,
+            input_generator(INPUT, 1).inspection_counts(1000).unwrap()
+        );
+
+        let relieved = input_generator(INPUT, 3);
+        assert_eq!(relieved.inspection_counts(20).unwrap(), [101, 95, 7, 105]);
+    }
+
+    #[test]
+    fn parallel() {
+        let serial = input_generator(INPUT, 1).inspection_counts(10000).unwrap();
+        for threads in [2, 3, 64] {
+            let monkeys = input_generator(INPUT, 1).with_threads(threads);
+            assert_eq!(monkeys.inspection_counts(10000).unwrap(), serial);
+        }
+        let relieved = input_generator(INPUT, 3).with_threads(4);
+        assert_eq!(relieved.inspection_counts(20).unwrap(), [101, 95, 7, 105]);
+        assert_eq!(solve_part2_threaded(INPUT, 4), Ok(2713310158));
+        assert!(Monkeys::from_notes(INPUT, 0).is_err());
+        assert!(Monkeys::from_notes("Monkey 0:", 1).is_err());
+    }
+
+    #[test]
+    fn narrative() {
+        let mut monkeys = Monkeys::from_notes(INPUT, 3).unwrap().with_log();
+        monkeys.do_round().unwrap();
+        let narrative = monkeys.narrative().unwrap();
+        assert!(narrative.starts_with(
+            "Monkey 0:
+  Monkey inspects an item with a worry level of 79.
+    Worry level is multiplied by 19 to 1501.
+    Monkey gets bored with item. Worry level is divided by 3 to 500.
+    Current worry level is not divisible by 23.
+    Item with worry level 500 is thrown to monkey 3.
+  Monkey inspects an item with a worry level of 98.
+"
+        ));
+        assert!(narrative.contains(
+            "  Monkey inspects an item with a worry level of 79.
+    Worry level is multiplied by itself to 6241.
+"
+        ));
+        assert!(narrative.contains("    Worry level increases by 6 to 71.\n"));
+        assert_eq!(narrative.lines().count(), monkeys.events().unwrap().len());
+
+        assert_eq!(
+            monkeys.holdings_report(),
+            "After round 1, the monkeys are holding items with these worry levels:
+Monkey 0: 20, 23, 27, 26
+Monkey 1: 2080, 25, 167, 207, 401, 1046
+Monkey 2: 
+Monkey 3: 
+"
+        );
+        for _ in 0..19 {
+            monkeys.do_round().unwrap();
+        }
+        assert_eq!(
+            monkeys.inspections_report(),
+            "== After round 20 ==
+Monkey 0 inspected items 101 times.
+Monkey 1 inspected items 95 times.
+Monkey 2 inspected items 7 times.
+Monkey 3 inspected items 105 times.
+"
+        );
+    }
+
+    #[test]
+    fn big_worry() {
+        let max = Worry::Small(usize::MAX);
+        let square = &max * &max;
+        assert_eq!(
+            square.to_string(),
+            "340282366920938463426481119284349108225"
+        );
+        assert_eq!(&square / usize::MAX, max);
+        assert_eq!((&square + &Worry::Small(1)).rem_small(usize::MAX), 1);
+        assert_eq!(square.checked_sub(&max).unwrap().checked_sub(&square), None);
+
+        let e = all_consuming(expr)("(old + 1) ^ 100 - old").unwrap().1;
+        let exact = e.evaluate(&Worry::Small(1), None).unwrap();
+        assert_eq!(exact.to_string(), "1267650600228229401496703205375");
+        let modulus = Worry::from(3u128.pow(70));
+        assert_eq!(
+            e.evaluate(&Worry::Small(1), Some(&modulus)).unwrap(),
+            &exact % &modulus
+        );
+
+        // Tests whose product overflows 64 bits agree with exact arithmetic
+        let notes = INPUT
+            .replacen("old * 19", "old * old", 1)
+            .replacen("by 23", "by 4294967291", 1)
+            .replacen("by 19", "by 4294967279", 1)
+            .replacen("by 13", "by 4294967231", 1)
+            .replacen("by 17", "by 4294967197", 1);
+        let mut modular = input_generator(&notes, 1);
+        assert!(matches!(modular.modulus, Worry::Big(_)));
+        let mut exact = input_generator(&notes, 1);
+        // Far bigger than anything a dozen rounds reach
+        exact.modulus = Worry::Big(BigUint(vec![1; 64]));
+        let modulus = modular.modulus.clone();
+        for _ in 0..12 {
+            modular.do_round().unwrap();
+            exact.do_round().unwrap();
+        }
+        let exact_items = exact.monkeys.iter().flat_map(|monkey| &monkey.items);
+        assert!(exact_items
+            .clone()
+            .any(|item| matches!(item, Worry::Big(_))));
+        for (modular, exact) in modular.monkeys.iter().zip(&exact.monkeys) {
+            assert_eq!(modular.inspected, exact.inspected);
+            let reduced: Vec<_> = exact.items.iter().map(|item| item % &modulus).collect();
+            assert_eq!(modular.items, reduced);
+        }
+    }
+
+    #[test]
+    fn expressions() {
+        let parse = |s: &str| all_consuming(expr)(s).unwrap().1;
+        assert_eq!(
+            parse("old * 19"),
+            Expr::Mul(Box::new(Expr::Old), Box::new(Expr::Number(19)))
+        );
+
+        let small = Worry::Small;
+        let e = parse("old * old + 3 * (old - 2) ^ 2 - 1");
+        assert_eq!(e.evaluate(&small(5), None).unwrap(), small(25 + 27 - 1));
+        assert_eq!(
+            e.evaluate(&small(5), Some(&small(7))).unwrap(),
+            small((25 + 27 - 1) % 7)
+        );
+        assert_eq!(
+            parse("2 ** 3 ^ 2").evaluate(&small(0), None).unwrap(),
+            small(512)
+        );
+        let nested = parse("(old ^ 3) ^ 2");
+        assert_eq!(nested.evaluate(&small(2), None).unwrap(), small(64));
+        assert_eq!(parse(&nested.to_string()), nested);
+        let source = "(old - 1) * (old + 2 * 3) - (1 - old ^ 2) ^ 3";
+        assert_eq!(parse(source).to_string(), source);
+        assert!(all_consuming(expr)("old ^ 2 ^ 40").is_err());
+        assert_eq!(
+            parse("old-10")
+                .evaluate(&small(3), Some(&small(96577)))
+                .unwrap(),
+            small(96577 - 7)
+        );
+
+        let big = parse("(old + 1) ^ 40");
+        let m = 23 * 19 * 13 * 17;
+        let mut expected = 1;
+        for _ in 0..40 {
+            expected = expected * 80 % m;
+        }
+        assert_eq!(
+            big.evaluate(&small(79), Some(&small(m))),
+            Ok(small(expected))
+        );
+
+        assert!(all_consuming(expr)("old +").is_err());
+        assert!(all_consuming(expr)("(old * 2").is_err());
+    }
+
+    #[test]
+    fn richer_operations() {
+        let notes = INPUT
+            .replacen("old * 19", "(old - 1) * 19 + 19", 1)
+            .replacen("old * old", "old ^ 2", 1);
+        assert_eq!(solve_part1(&notes), Ok(10605));
+        assert_eq!(solve_part2(&notes), Ok(2713310158));
+
+        // Exact worry levels can't go negative, but modular ones wrap around
+        let negative = INPUT.replacen("old + 6", "old - 100", 1);
+        let error = solve_part1(&negative).unwrap_err();
+        assert!(
+            error.starts_with("monkey 1: worry level went negative"),
+            "{error}"
+        );
+        assert!(solve_part2(&negative).is_ok());
+    }
+
+    #[test]
+    fn strict_parser() {
+        assert_eq!(parse_notes(INPUT).unwrap().len(), 4);
+        assert_eq!(parse_notes(INPUT.trim_end()).unwrap().len(), 4);
+
+        let swapped = INPUT.replacen(
+            "  Operation: new = old * 19\n  Test: divisible by 23",
+            "  Test: divisible by 23\n  Operation: new = old * 19",
+            1,
+        );
+        let error = parse_notes(&swapped).unwrap_err();
+        assert!(error.contains("in operation"), "{error}");
+        assert!(error.contains("line 3"), "{error}");
+
+        let bad_operator = INPUT.replacen("old * 19", "old / 19", 1);
+        let error = parse_notes(&bad_operator).unwrap_err();
+        assert!(error.contains("in operation"), "{error}");
+        assert!(
+            error.contains("old / 19\n                       ^"),
+            "{error}"
+        );
+
+        let bad_operand = INPUT.replacen("old + 6", "old + foo", 1);
+        let error = parse_notes(&bad_operand).unwrap_err();
+        assert!(
+            error.contains("old + foo\n                         ^"),
+            "{error}"
+        );
+        let bad_factor = INPUT.replacen("old * 19", "old * foo", 1);
+        let error = parse_notes(&bad_factor).unwrap_err();
+        assert!(
+            error.contains("old * foo\n                         ^"),
+            "{error}"
+        );
+
+        let missing_target = INPUT.replacen("throw to monkey 3", "throw to monkey 7", 1);
+        assert_eq!(
+            parse_notes(&missing_target).unwrap_err(),
+            "monkey 0 throws to missing monkey 7"
+        );
+
+        let renumbered = INPUT.replacen("Monkey 1:", "Monkey 5:", 1);
+        assert_eq!(
+            parse_notes(&renumbered).unwrap_err(),
+            "expected monkey 1, found monkey 5"
+        );
     }
 }
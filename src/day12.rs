@@ -36,7 +36,19 @@ pub fn input_generator(input: &str) -> Input {
     )
 }
 
+// Which way steps are taken: Up walks a route forwards, climbing at most one
+// level per step, and Down walks it backwards from the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Climb {
+    Up,
+    Down,
+}
+
 fn valid_neighbours(heightmap: &HeightMap, point: Point) -> Vec<Point> {
+    neighbours(heightmap, point, Climb::Up)
+}
+
+fn neighbours(heightmap: &HeightMap, point: Point, climb: Climb) -> Vec<Point> {
     let point_value = *heightmap.get(point.1, point.0).unwrap() as isize;
     let mut neighbours = Vec::new();
     for i in -1..=1isize {
@@ -47,7 +59,8 @@ fn valid_neighbours(heightmap: &HeightMap, point: Point) -> Vec<Point> {
                 continue;
             }
             if let Some(neighbour) = heightmap.get(y as usize, x as usize) {
-                if *neighbour as isize - point_value <= 1 {
+                let rise = *neighbour as isize - point_value;
+                if (climb == Climb::Up && rise <= 1) || (climb == Climb::Down && rise >= -1) {
                     neighbours.push(Point(x as usize, y as usize));
                 }
             }
@@ -56,7 +69,9 @@ fn valid_neighbours(heightmap: &HeightMap, point: Point) -> Vec<Point> {
     neighbours
 }
 
-fn bfs<F>(heightmap: &HeightMap, start: Start, end_condition: F) -> usize
+// The route from start to the first point satisfying end_condition, both
+// included
+fn bfs<F>(heightmap: &HeightMap, start: Start, end_condition: F) -> Option<Vec<Point>>
 where
     F: FnOnce(Point) -> bool + Copy,
 {
@@ -86,32 +101,154 @@ where
         }
     }
 
-    let mut path_length = 0;
-    let mut path_node = end;
+    let mut path = Vec::new();
+    let mut path_node = Some(end?);
     while let Some(node) = path_node {
-        path_length += 1;
+        path.push(node);
         path_node = *parents.get(node.1, node.0).unwrap();
     }
+    path.reverse();
+
+    Some(path)
+}
+
+pub fn shortest_path(heightmap: &HeightMap, start: Start, end: End) -> Option<Vec<Point>> {
+    bfs(heightmap, start, |p| p == end)
+}
+
+// The route drawn as in the puzzle, with an arrow on each step showing which
+// way it leaves, E at the end and dots everywhere else
+pub fn render_route(heightmap: &HeightMap, path: &[Point]) -> String {
+    let mut canvas = Grid::init(heightmap.rows(), heightmap.cols(), '.');
+    for step in path.windows(2) {
+        let (from, to) = (step[0], step[1]);
+        let arrow = if to.0 > from.0 {
+            '>'
+        } else if to.0 < from.0 {
+            '<'
+        } else if to.1 < from.1 {
+            '^'
+        } else {
+            'v'
+        };
+        *canvas.get_mut(from.1, from.0).unwrap() = arrow;
+    }
+    if let Some(end) = path.last() {
+        *canvas.get_mut(end.1, end.0).unwrap() = 'E';
+    }
+
+    let mut rendered = String::new();
+    for row in 0..canvas.rows() {
+        rendered.extend(canvas.iter_row(row));
+        rendered.push('\n');
+    }
+    rendered
+}
 
-    path_length - 1
+// Every point at the given height
+pub fn points_at(heightmap: &HeightMap, height: u8) -> Vec<Point> {
+    let mut points = Vec::new();
+    for y in 0..heightmap.rows() {
+        for (x, &h) in heightmap.iter_row(y).enumerate() {
+            if h == height {
+                points.push(Point(x, y));
+            }
+        }
+    }
+    points
+}
+
+// Steps from the nearest source to every point, or None where no source can
+// reach. With Climb::Down this is the distance from every point to the nearest
+// source instead.
+pub fn distance_field(
+    heightmap: &HeightMap,
+    sources: &[Point],
+    climb: Climb,
+) -> Grid<Option<usize>> {
+    let mut distances = Grid::init(heightmap.rows(), heightmap.cols(), None);
+    let mut q = VecDeque::new();
+    for &source in sources {
+        *distances.get_mut(source.1, source.0).unwrap() = Some(0);
+        q.push_back(source);
+    }
+
+    while let Some(v) = q.pop_front() {
+        let distance = distances.get(v.1, v.0).unwrap().unwrap();
+        for w in neighbours(heightmap, v, climb) {
+            let cell = distances.get_mut(w.1, w.0).unwrap();
+            if cell.is_none() {
+                *cell = Some(distance + 1);
+                q.push_back(w);
+            }
+        }
+    }
+    distances
+}
+
+// The distance field from every point in turn, row by row. Each one is only
+// computed when asked for, since keeping them all takes memory quadratic in
+// the size of the map.
+pub fn all_pairs(
+    heightmap: &HeightMap,
+    climb: Climb,
+) -> impl Iterator<Item = (Point, Grid<Option<usize>>)> + '_ {
+    (0..heightmap.rows())
+        .flat_map(move |y| (0..heightmap.cols()).map(move |x| Point(x, y)))
+        .map(move |source| (source, distance_field(heightmap, &[source], climb)))
+}
+
+pub type Rgb = (u8, u8, u8);
+
+// Blue through green to red as value goes from 0 to max
+fn heat_colour(value: usize, max: usize) -> Rgb {
+    let t = if max == 0 {
+        0.0
+    } else {
+        value as f64 / max as f64
+    };
+    let (r, g, b) = if t < 0.5 {
+        (0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        (2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    };
+    (
+        (r * 255.0).round() as u8,
+        (g * 255.0).round() as u8,
+        (b * 255.0).round() as u8,
+    )
+}
+
+// Near points in blue through to far ones in red, unreachable ones black
+pub fn distance_heatmap(distances: &Grid<Option<usize>>) -> Grid<Rgb> {
+    let max = distances.iter().flatten().copied().max().unwrap_or(0);
+    let mut image = Grid::init(distances.rows(), distances.cols(), (0, 0, 0));
+    for (pixel, distance) in image.iter_mut().zip(distances.iter()) {
+        if let Some(distance) = distance {
+            *pixel = heat_colour(*distance, max);
+        }
+    }
+    image
 }
 
 #[aoc(day12, part1)]
 fn solve_part1(input: &Input) -> usize {
     let (heightmap, start, end) = input;
-    bfs(heightmap, *start, |p| p == *end)
+    shortest_path(heightmap, *start, *end)
+        .expect("no route to the end")
+        .len()
+        - 1
 }
 
 #[aoc(day12, part2)]
 fn solve_part2(input: &Input) -> usize {
     let (heightmap, _start, end) = input;
-    let mut heightmap = heightmap.clone();
-    for height in heightmap.iter_mut() {
-        *height = 25u8 - *height;
-    }
-    bfs(&heightmap, *end, |p| {
-        heightmap.get(p.1, p.0).unwrap() == &25u8
-    })
+    let distances = distance_field(heightmap, &[*end], Climb::Down);
+    points_at(heightmap, 0)
+        .into_iter()
+        .filter_map(|p| *distances.get(p.1, p.0).unwrap())
+        .min()
+        .expect("no route to the end")
 }
 
 #[cfg(test)]
@@ -129,4 +266,56 @@ mod tests {
     fn part2() {
         assert_eq!(solve_part2(&input_generator(INPUT)), 29);
     }
+
+    #[test]
+    fn distance_fields() {
+        let (heightmap, start, end) = input_generator(INPUT);
+        let to_end = distance_field(&heightmap, &[end], Climb::Down);
+        let from_start = distance_field(&heightmap, &[start], Climb::Up);
+        assert_eq!(to_end.get(start.1, start.0), Some(&Some(31)));
+        assert_eq!(from_start.get(end.1, end.0), Some(&Some(31)));
+        assert_eq!(to_end.get(end.1, end.0), Some(&Some(0)));
+
+        // From every a at once gives part 2 directly
+        let from_a = distance_field(&heightmap, &points_at(&heightmap, 0), Climb::Up);
+        assert_eq!(from_a.get(end.1, end.0), Some(&Some(29)));
+
+        assert_eq!(all_pairs(&heightmap, Climb::Up).count(), 5 * 8);
+        let (source, field) = all_pairs(&heightmap, Climb::Up).next().unwrap();
+        assert_eq!((source, field), (start, from_start));
+
+        let cliff = input_generator("Sz\naE\n").0;
+        let field = distance_field(&cliff, &[Point(0, 0)], Climb::Up);
+        assert_eq!(field.get(0, 1), Some(&None));
+        let image = distance_heatmap(&field);
+        assert_eq!(image.get(0, 0), Some(&(0, 0, 255)));
+        assert_eq!(image.get(0, 1), Some(&(0, 0, 0)));
+        assert_eq!(image.get(1, 0), Some(&(255, 0, 0)));
+    }
+
+    #[test]
+    fn route() {
+        let (heightmap, start, end) = input_generator(INPUT);
+        let path = shortest_path(&heightmap, start, end).unwrap();
+        assert_eq!(path.len(), 32);
+        assert_eq!((path[0], path[31]), (start, end));
+        for step in path.windows(2) {
+            assert!(valid_neighbours(&heightmap, step[0]).contains(&step[1]));
+        }
+
+        let rendered = render_route(&heightmap, &path);
+        assert_eq!(rendered.lines().count(), 5);
+        assert_eq!(rendered.matches(['<', '>', '^', 'v']).count(), 31);
+        assert_eq!(rendered.lines().nth(2).unwrap().chars().nth(5), Some('E'));
+
+        let input = "SbcdefghijklmnopqrstuvwxyE\nzzzzzzzzzzzzzzzzzzzzzzzzzz\n";
+        let (heightmap, start, end) = input_generator(input);
+        assert_eq!(
+            render_route(&heightmap, &shortest_path(&heightmap, start, end).unwrap()),
+            ">".repeat(25) + "E\n" + &".".repeat(26) + "\n"
+        );
+
+        let (heightmap, start, end) = input_generator("Sz\nzE\n");
+        assert_eq!(shortest_path(&heightmap, start, end), None);
+    }
 }